@@ -0,0 +1,92 @@
+use anyhow::Result;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rust_learning::matrix::{dot_product, multiply, Matrix, Pool, Vector};
+use std::{hint::black_box, sync::mpsc, thread};
+
+const SIZES: [usize; 2] = [512, 1024];
+const NUM_THREADS: usize = 4;
+
+struct Msg {
+    idx: usize,
+    row: Vector<f64>,
+    col: Vector<f64>,
+    sender: oneshot::Sender<(usize, f64)>,
+}
+
+// the previous implementation: fresh threads per call, one message per cell carrying copies of
+// its row and column
+fn per_cell(a: &Matrix<f64>, b: &Matrix<f64>) -> Result<Matrix<f64>> {
+    let senders = (0..NUM_THREADS)
+        .map(|_| {
+            let (tx, rx) = mpsc::channel::<Msg>();
+            thread::spawn(move || {
+                for msg in rx {
+                    let val = dot_product(msg.row, msg.col)?;
+                    let _ = msg.sender.send((msg.idx, val));
+                }
+                Ok::<_, anyhow::Error>(())
+            });
+            tx
+        })
+        .collect::<Vec<_>>();
+
+    let (row, len, col) = (a.row(), a.col(), b.col());
+    let mut receivers = Vec::with_capacity(row * col);
+    for i in 0..row {
+        for j in 0..col {
+            let idx = i * col + j;
+            let (sender, rx) = oneshot::channel();
+            let msg = Msg {
+                idx,
+                row: Vector::new(&a.data()[i * len..(i + 1) * len]),
+                col: Vector::new(
+                    b.data()[j..]
+                        .iter()
+                        .step_by(col)
+                        .copied()
+                        .collect::<Vec<_>>(),
+                ),
+                sender,
+            };
+            senders[idx % NUM_THREADS].send(msg)?;
+            receivers.push(rx);
+        }
+    }
+
+    let mut data = vec![0.0; row * col];
+    for rx in receivers {
+        let (idx, val) = rx.recv()?;
+        data[idx] = val;
+    }
+    Ok(Matrix::new(data, row, col))
+}
+
+fn square(size: usize) -> Matrix<f64> {
+    let data = (0..size * size)
+        .map(|i| (i % 17) as f64)
+        .collect::<Vec<_>>();
+    Matrix::new(data, size, size)
+}
+
+fn bench_multiply(c: &mut Criterion) {
+    let mut group = c.benchmark_group("multiply");
+    group.sample_size(10);
+    // start the shared pool outside the measurements
+    Pool::global();
+
+    for size in SIZES {
+        let (a, b) = (square(size), square(size));
+        assert!(per_cell(&a, &b).unwrap() == multiply(&a, &b).unwrap());
+
+        group.bench_with_input(BenchmarkId::new("per_cell", size), &size, |bench, _| {
+            bench.iter(|| per_cell(black_box(&a), black_box(&b)).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("pool", size), &size, |bench, _| {
+            bench.iter(|| multiply(black_box(&a), black_box(&b)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_multiply);
+criterion_main!(benches);
@@ -1,15 +1,13 @@
 use anyhow::{Ok, Result};
 use axum::routing::get;
 use axum::Router;
-use once_cell::sync::Lazy;
-use opentelemetry::{global, KeyValue};
+use opentelemetry::global;
 use opentelemetry_appender_tracing::layer::OpenTelemetryTracingBridge;
-use opentelemetry_otlp::{LogExporter, MetricExporter, WithExportConfig};
+use opentelemetry_otlp::{LogExporter, WithExportConfig};
 use opentelemetry_sdk::logs::LoggerProvider;
-use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
 use opentelemetry_sdk::trace::{RandomIdGenerator, TracerProvider};
-use opentelemetry_sdk::{runtime, trace, Resource};
-use opentelemetry_semantic_conventions::resource;
+use opentelemetry_sdk::{runtime, trace};
+use rust_learning::telemetry;
 use tokio::net::TcpListener;
 use tracing::{info, instrument, level_filters::LevelFilter};
 use tracing_subscriber::util::SubscriberInitExt;
@@ -19,13 +17,7 @@ use tracing_subscriber::{
     Layer,
 };
 
-static RESOURCE: Lazy<Resource> = Lazy::new(|| {
-    Resource::new(vec![
-        KeyValue::new(resource::SERVICE_NAME, "axum-tracing-example"),
-        KeyValue::new(resource::SERVICE_VERSION, "0.0.1"),
-        KeyValue::new(resource::SERVICE_NAMESPACE, "jrmarcco"),
-    ])
-});
+const OTLP_ENDPOINT: &str = "http://localhost:4317";
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -43,7 +35,7 @@ async fn main() -> Result<()> {
     let tracer_provider = init_tracer_provider()?;
     global::set_tracer_provider(tracer_provider.clone());
 
-    let metrics_provider = init_metrics()?;
+    let metrics_provider = telemetry::meter_provider(None, Some(OTLP_ENDPOINT))?;
     global::set_meter_provider(metrics_provider.clone());
 
     let logger_provider = init_logs()?;
@@ -67,35 +59,24 @@ async fn main() -> Result<()> {
 fn init_tracer_provider() -> Result<TracerProvider> {
     let exporter = opentelemetry_otlp::SpanExporter::builder()
         .with_tonic()
-        .with_endpoint("http://localhost:4317")
+        .with_endpoint(OTLP_ENDPOINT)
         .build()?;
 
     Ok(trace::TracerProvider::builder()
-        .with_resource(RESOURCE.clone())
+        .with_resource(telemetry::resource())
         .with_id_generator(RandomIdGenerator::default())
         .with_batch_exporter(exporter, runtime::Tokio)
         .build())
 }
 
-fn init_metrics() -> Result<SdkMeterProvider> {
-    let exporter = MetricExporter::builder().with_tonic().build()?;
-
-    let reader = PeriodicReader::builder(exporter, runtime::Tokio).build();
-
-    Ok(SdkMeterProvider::builder()
-        .with_reader(reader)
-        .with_resource(RESOURCE.clone())
-        .build())
-}
-
 fn init_logs() -> Result<LoggerProvider> {
     let exporter = LogExporter::builder()
         .with_tonic()
-        .with_endpoint("http://localhost:4317")
+        .with_endpoint(OTLP_ENDPOINT)
         .build()?;
 
     Ok(LoggerProvider::builder()
-        .with_resource(RESOURCE.clone())
+        .with_resource(telemetry::resource())
         .with_batch_exporter(exporter, runtime::Tokio)
         .build())
 }
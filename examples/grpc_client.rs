@@ -1,9 +1,5 @@
 use anyhow::Result;
-use pb::{user_service_client::UserServiceClient, CreateUserReq, GetUserReq};
-
-pub mod pb {
-    include!(concat!("../src/pb", "/demo.rs"));
-}
+use rust_learning::pb::{user_service_client::UserServiceClient, CreateUserReq, GetUserReq};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -1,14 +1,8 @@
 use anyhow::{anyhow, Result};
-
-#[macro_export]
-macro_rules! j_try {
-    ($expr:expr) => {
-        match $expr {
-            Ok(val) => val,
-            Err(err) => return Err(err.into()),
-        }
-    };
-}
+use rust_learning::j_try;
+use std::collections::HashMap;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{fmt::Layer, layer::SubscriberExt, util::SubscriberInitExt, Layer as _};
 
 fn first(s: impl AsRef<str>) -> Result<String> {
     Ok(format!("first: {}", s.as_ref()))
@@ -22,8 +16,26 @@ fn third(s: impl AsRef<str>) -> Result<String> {
     Err(anyhow!("third: {}", s.as_ref()))
 }
 
+fn load_user(users: &HashMap<u64, String>, id: u64) -> Result<String> {
+    let name = j_try!(users.get(&id), else anyhow!("user {} not found", id));
+    let res = j_try!(third(name), "loading user {}", id);
+    Ok(res)
+}
+
 fn main() -> Result<()> {
-    let res = j_try!(third(j_try!(second(j_try!(first("jrmarcco"))))));
+    let layer = Layer::new().pretty().with_filter(LevelFilter::DEBUG);
+    tracing_subscriber::registry().with(layer).init();
+
+    // print every propagation point in the final error
+    rust_learning::set_breadcrumbs(true);
+
+    let users = HashMap::from([(1, "jrmarcco".to_string())]);
+    if let Err(e) = load_user(&users, 2) {
+        println!("Error: {:?}", e);
+    }
+
+    let name = j_try!(load_user(&users, 1));
+    let res = j_try!(third(j_try!(second(j_try!(first(name))))));
     println!("Final result: {res}");
     Ok(())
 }
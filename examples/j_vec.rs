@@ -1,17 +1,6 @@
 use anyhow::Result;
-
-#[macro_export]
-macro_rules! j_vec {
-    () => {
-        Vec::new()
-    };
-    ($elem:expr; $n:expr) => {
-        std::vec::from_elem($elem, $n)
-    };
-    ($($x:expr),+ $(,)?) => {{
-        <[_]>::into_vec(Box::new([$($x),*]))
-    }}
-}
+use rust_learning::{j_btree, j_deque, j_map, j_set, j_vec};
+use std::collections::HashMap;
 
 fn main() -> Result<()> {
     let val: Vec<i32> = j_vec![
@@ -22,7 +11,22 @@ fn main() -> Result<()> {
         "5".parse()?,
         "6".parse()?,
     ];
+    println!("{:?}", val);
 
+    // collect every element's error into a single result instead of `?` on each
+    let val: Vec<i32> = j_vec![try "1".parse(), "2".parse(), "3".parse()]?;
     println!("{:?}", val);
+
+    let map: HashMap<&str, u8> = j_map! {
+        try
+        "rust" => "1".parse(),
+        "go" => "2".parse(),
+    }?;
+    println!("{:?}", map);
+
+    println!("{:?}", j_btree! { "b" => 2, "a" => 1 });
+    println!("{:?}", j_set!["rust", "go", "rust"]);
+    println!("{:?}", j_deque![1, 2, 3]);
+
     Ok(())
 }
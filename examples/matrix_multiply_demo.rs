@@ -1,204 +1,4 @@
-use std::fmt::Formatter;
-use std::ops::{Add, AddAssign, Deref, Mul};
-use std::sync::mpsc;
-use std::{fmt, thread};
-
-use anyhow::{anyhow, Result};
-
-struct Vector<T> {
-    data: Vec<T>,
-}
-
-impl<T> Deref for Vector<T> {
-    type Target = Vec<T>;
-
-    fn deref(&self) -> &Self::Target {
-        &self.data
-    }
-}
-
-impl<T> Vector<T> {
-    fn new(data: impl Into<Vec<T>>) -> Self {
-        Self { data: data.into() }
-    }
-}
-
-struct Matrix<T> {
-    data: Vec<T>,
-    row: usize,
-    col: usize,
-}
-
-impl<T: fmt::Debug> Matrix<T> {
-    fn new(data: impl Into<Vec<T>>, row: usize, col: usize) -> Self {
-        Self {
-            data: data.into(),
-            row,
-            col,
-        }
-    }
-}
-
-impl<T> fmt::Display for Matrix<T>
-where
-    T: fmt::Display,
-{
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{{")?;
-
-        for i in 0..self.row {
-            for j in 0..self.col {
-                write!(f, "{}", self.data[i * self.col + j])?;
-
-                if j != self.col - 1 {
-                    write!(f, ", ")?;
-                }
-            }
-
-            if i != self.row - 1 {
-                write!(f, ", ")?;
-            }
-        }
-
-        write!(f, "}}")?;
-        Ok(())
-    }
-}
-
-impl<T> fmt::Debug for Matrix<T>
-where
-    T: fmt::Display,
-{
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "Matrix(row={}, col={}, {})", self.row, self.col, self)
-    }
-}
-
-struct MsgIn<T> {
-    idx: usize,
-    row: Vector<T>,
-    col: Vector<T>,
-}
-
-impl<T> MsgIn<T> {
-    fn new(idx: usize, row: Vector<T>, col: Vector<T>) -> Self {
-        Self { idx, row, col }
-    }
-}
-
-struct MsgOut<T> {
-    idx: usize,
-    val: T,
-}
-
-struct Msg<T> {
-    input: MsgIn<T>,
-    sender: oneshot::Sender<MsgOut<T>>,
-}
-
-impl<T> Msg<T> {
-    fn new(input: MsgIn<T>, sender: oneshot::Sender<MsgOut<T>>) -> Self {
-        Self { input, sender }
-    }
-}
-
-impl<T> Mul for Matrix<T>
-where
-    T: Copy + Default + Add<Output = T> + AddAssign + Mul<Output = T> + Send + 'static,
-{
-    type Output = Self;
-
-    fn mul(self, rhs: Self) -> Self::Output {
-        multiply(&self, &rhs).expect("Matrix multiply error.")
-    }
-}
-
-const NUM_THREADS: usize = 4;
-
-fn multiply<T>(a: &Matrix<T>, b: &Matrix<T>) -> Result<Matrix<T>>
-where
-    T: Copy + Default + Add<Output = T> + AddAssign + Mul<Output = T> + Send + 'static,
-{
-    if a.col != b.row {
-        return Err(anyhow!("Matrix multiply error: a.col != b.row."));
-    }
-
-    let senders = (0..NUM_THREADS)
-        .map(|_| {
-            let (tx, rx) = mpsc::channel::<Msg<T>>();
-
-            thread::spawn(move || {
-                for msg in rx {
-                    let val = dot_product(msg.input.row, msg.input.col)?;
-                    if let Err(e) = msg.sender.send(MsgOut {
-                        idx: msg.input.idx,
-                        val,
-                    }) {
-                        eprintln!("Send error: {:?}", e)
-                    }
-                }
-
-                Ok::<_, anyhow::Error>(())
-            });
-            tx
-        })
-        .collect::<Vec<_>>();
-
-    let matrix_len = a.row * b.col;
-
-    let mut data = vec![T::default(); matrix_len];
-    let mut receivers = Vec::with_capacity(matrix_len);
-
-    for i in 0..a.row {
-        for j in 0..b.col {
-            let row = Vector::new(&a.data[i * a.col..(i + 1) * a.col]);
-            let col_data = b.data[j..]
-                .iter()
-                .step_by(b.col)
-                .copied()
-                .collect::<Vec<_>>();
-
-            let col = Vector::new(col_data);
-            let idx = i * b.col + j;
-            let input = MsgIn::new(idx, row, col);
-            let (tx, rx) = oneshot::channel();
-            let msg = Msg::new(input, tx);
-
-            if let Err(e) = senders[idx % NUM_THREADS].send(msg) {
-                eprintln!("Send error: {:?}", e);
-            }
-
-            receivers.push(rx);
-        }
-    }
-
-    for rx in receivers {
-        let output = rx.recv()?;
-        data[output.idx] = output.val;
-    }
-
-    Ok(Matrix {
-        data,
-        row: a.row,
-        col: b.col,
-    })
-}
-
-fn dot_product<T>(a: Vector<T>, b: Vector<T>) -> Result<T>
-where
-    T: Copy + Default + Add<Output = T> + AddAssign + Mul<Output = T>,
-{
-    if a.len() != b.len() {
-        return Err(anyhow!("Dot product error: a.len() != b.len()."));
-    }
-
-    let mut sum = T::default();
-    for i in 0..a.len() {
-        sum += a[i] * b[i];
-    }
-
-    Ok(sum)
-}
+use rust_learning::matrix::Matrix;
 
 fn main() {
     let a = Matrix::new([1, 2, 3, 4, 5, 6], 2, 3);
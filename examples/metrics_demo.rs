@@ -1,40 +1,8 @@
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
 use std::thread;
 use std::time::Duration;
 
-use anyhow::{anyhow, Result};
 use rand::Rng;
-
-#[derive(Debug, Clone)]
-struct Metrics {
-    data: Arc<RwLock<HashMap<String, u64>>>,
-}
-
-impl Metrics {
-    fn new() -> Self {
-        Self {
-            data: Arc::new(RwLock::new(HashMap::new())),
-        }
-    }
-
-    fn incr(&self, key: impl Into<String>, delta: u64) -> Result<()> {
-        let mut data = self.data.write().map_err(|e| anyhow!(e.to_string()))?;
-        let counter = data.entry(key.into()).or_insert(0);
-        *counter += delta;
-
-        Ok(())
-    }
-
-    fn snapshot(&self) -> Result<HashMap<String, u64>> {
-        Ok(self
-            .data
-            .read()
-            .map_err(|e| anyhow!(e.to_string()))
-            .unwrap()
-            .clone())
-    }
-}
+use rust_learning::metrics::Metrics;
 
 #[allow(unreachable_code)]
 fn main() {
@@ -1,16 +1,10 @@
 use anyhow::Result;
 use base64::prelude::BASE64_URL_SAFE_NO_PAD;
 use base64::Engine;
-use chacha20poly1305::aead::{Aead, OsRng};
-use chacha20poly1305::{AeadCore, ChaCha20Poly1305, KeyInit};
 use chrono::{DateTime, Utc};
+use rust_learning::sensitive::SensitiveData;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_with::{serde_as, DisplayFromStr};
-use std::fmt;
-use std::fmt::Formatter;
-use std::str::FromStr;
-
-const KEY: &[u8] = b"01234567890123456789012345678901";
 
 #[serde_as]
 #[derive(Debug, Serialize, Deserialize)]
@@ -57,45 +51,6 @@ where
     Ok(decoded)
 }
 
-#[derive(Debug)]
-struct SensitiveData(String);
-
-impl fmt::Display for SensitiveData {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        let cipher = ChaCha20Poly1305::new(KEY.into());
-        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
-
-        let cipher_text = cipher.encrypt(&nonce, self.0.as_bytes()).unwrap();
-        let nonce_cipher_text: Vec<_> = nonce.iter().copied().chain(cipher_text).collect();
-
-        let encoded = BASE64_URL_SAFE_NO_PAD.encode(nonce_cipher_text);
-
-        write!(f, "{}", encoded)
-    }
-}
-
-impl FromStr for SensitiveData {
-    type Err = anyhow::Error;
-
-    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        let decoded = BASE64_URL_SAFE_NO_PAD.decode(s.as_bytes())?;
-        let cipher = ChaCha20Poly1305::new(KEY.into());
-
-        let nonce = decoded[..12].into();
-
-        let decrypted = cipher.decrypt(nonce, &decoded[12..]).unwrap();
-        let decrypted = String::from_utf8(decrypted)?;
-
-        Ok(Self(decrypted))
-    }
-}
-
-impl SensitiveData {
-    fn new(data: impl Into<String>) -> Self {
-        Self(data.into())
-    }
-}
-
 fn main() -> Result<()> {
     let bar = Bar::OnLeave(Utc::now());
     let urls = vec![
@@ -19,6 +19,8 @@ impl Msg {
     }
 }
 
+// newer clippy flags `x % n == 0`, the demo keeps it
+#[allow(clippy::manual_is_multiple_of)]
 fn producer(idx: usize, tx: mpsc::Sender<Msg>) -> Result<()> {
     loop {
         let val = rand::random::<u64>();
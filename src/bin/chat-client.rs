@@ -0,0 +1,51 @@
+use clap::Parser;
+use futures::TryStreamExt;
+use rust_learning::chat::Client;
+use std::{
+    io::{self, IsTerminal},
+    process::ExitCode,
+};
+use tokio_util::codec::{FramedRead, LinesCodec};
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "chat-client",
+    version,
+    about = "Terminal client for the chat server"
+)]
+struct Opts {
+    /// Username to log in with
+    username: String,
+
+    /// Address of the chat server
+    #[arg(short, long, default_value = "127.0.0.1:3000")]
+    server: String,
+
+    /// Password of a registered username, prompted for by the server when omitted
+    #[arg(long)]
+    password: Option<String>,
+
+    /// Print without colors, the default when stdout is not a terminal
+    #[arg(long)]
+    no_color: bool,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let opts = Opts::parse();
+
+    let color = !opts.no_color && io::stdout().is_terminal();
+    let mut client = Client::new(opts.server, opts.username).with_color(color);
+    if let Some(password) = opts.password {
+        client = client.with_password(password);
+    }
+
+    let input = FramedRead::new(tokio::io::stdin(), LinesCodec::new()).err_into();
+    match client.run(input, tokio::io::stdout()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
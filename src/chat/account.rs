@@ -0,0 +1,239 @@
+use anyhow::{anyhow, Result};
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::store::JsonFile;
+
+const MIN_PASSWORD_LEN: usize = 8;
+
+// passwords hashed at once, each hash takes about 19 MiB with the default argon2 params
+const MAX_HASHING: usize = 4;
+
+// failed logins from one address before it has to wait between attempts, doubling each time
+const FREE_FAILURES: u32 = 3;
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// an address quiet for this long starts over
+const FORGET_FAILURES: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Account {
+    // as registered, lookups ignore case
+    pub username: String,
+    // argon2 PHC string, salt included
+    pub hash: String,
+    pub registered_at: DateTime<Utc>,
+}
+
+// registered usernames, saved to a JSON file on every change when a path is set
+#[derive(Debug)]
+pub struct Accounts {
+    file: Option<JsonFile>,
+    accounts: Mutex<HashMap<String, Account>>,
+    // hashing is costly and clients can trigger it before logging in
+    hashing: Arc<Semaphore>,
+    failures: DashMap<IpAddr, Failures>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Failures {
+    count: u32,
+    last: Instant,
+}
+
+impl Default for Accounts {
+    fn default() -> Self {
+        Self::new(None, HashMap::new())
+    }
+}
+
+impl Failures {
+    // how long after the last failure the next attempt is allowed
+    fn backoff(&self) -> Option<Duration> {
+        let doublings = self.count.checked_sub(FREE_FAILURES + 1)?;
+        Some(Duration::from_secs(1 << doublings.min(6)).min(MAX_BACKOFF))
+    }
+}
+
+impl Accounts {
+    // load accounts from `path`, a missing file means no accounts yet
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        // holds password hashes, keep it away from other users
+        let file = JsonFile::new(path, "account file").private();
+        let accounts = file
+            .load::<Vec<Account>>()?
+            .into_iter()
+            .map(|account| (account.username.to_lowercase(), account))
+            .collect();
+        Ok(Self::new(Some(file), accounts))
+    }
+
+    fn new(file: Option<JsonFile>, accounts: HashMap<String, Account>) -> Self {
+        Self {
+            file,
+            accounts: Mutex::new(accounts),
+            hashing: Arc::new(Semaphore::new(MAX_HASHING)),
+            failures: DashMap::new(),
+        }
+    }
+
+    pub fn is_registered(&self, username: &str) -> bool {
+        self.accounts
+            .lock()
+            .unwrap()
+            .contains_key(&username.to_lowercase())
+    }
+
+    // hashing is slow on purpose, call it off the async runtime
+    pub fn register(&self, username: &str, password: &str) -> Result<()> {
+        if password.chars().count() < MIN_PASSWORD_LEN {
+            return Err(anyhow!(
+                "Password must be at least {} characters",
+                MIN_PASSWORD_LEN
+            ));
+        }
+        if self.is_registered(username) {
+            return Err(anyhow!("Username {} is already registered", username));
+        }
+
+        let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>())
+            .map_err(|e| anyhow!("Failed to encode salt: {}", e))?;
+        let hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|e| anyhow!("Failed to hash password: {}", e))?
+            .to_string();
+
+        let mut accounts = self.accounts.lock().unwrap();
+        // lost a race with another registration while hashing
+        if accounts.contains_key(&username.to_lowercase()) {
+            return Err(anyhow!("Username {} is already registered", username));
+        }
+        accounts.insert(
+            username.to_lowercase(),
+            Account {
+                username: username.to_string(),
+                hash,
+                registered_at: Utc::now(),
+            },
+        );
+        self.save(&accounts)
+    }
+
+    // false for unknown users too; slow like `register`
+    pub fn verify(&self, username: &str, password: &str) -> bool {
+        let hash = match self.accounts.lock().unwrap().get(&username.to_lowercase()) {
+            Some(account) => account.hash.clone(),
+            None => return false,
+        };
+        PasswordHash::new(&hash).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    }
+
+    // wait for a turn to hash, the permit is held until the hash is done
+    pub async fn permit(&self) -> OwnedSemaphorePermit {
+        self.hashing
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the hashing semaphore is never closed")
+    }
+
+    // how much longer `ip` has to wait before its next login attempt
+    pub fn backoff(&self, ip: IpAddr) -> Option<Duration> {
+        let failures = *self.failures.get(&ip)?;
+        failures.backoff()?.checked_sub(failures.last.elapsed())
+    }
+
+    pub fn failed(&self, ip: IpAddr) {
+        let now = Instant::now();
+        self.failures
+            .retain(|_, failures| now.duration_since(failures.last) < FORGET_FAILURES);
+        let mut failures = self.failures.entry(ip).or_insert(Failures {
+            count: 0,
+            last: now,
+        });
+        failures.count += 1;
+        failures.last = now;
+    }
+
+    pub fn succeeded(&self, ip: IpAddr) {
+        self.failures.remove(&ip);
+    }
+
+    fn save(&self, accounts: &HashMap<String, Account>) -> Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+
+        let mut accounts = accounts.values().collect::<Vec<_>>();
+        accounts.sort_by(|a, b| a.username.cmp(&b.username));
+        file.save(&accounts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn accounts_should_verify_and_persist() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("accounts-{}.json", nanoid::nanoid!()));
+        let accounts = Accounts::load(&path)?;
+
+        assert!(accounts.register("Alice", "short").is_err());
+        accounts.register("Alice", "correct horse")?;
+        assert!(accounts.register("alice", "battery staple").is_err());
+
+        let accounts = Accounts::load(&path)?;
+        assert!(accounts.is_registered("ALICE"));
+        assert!(accounts.verify("alice", "correct horse"));
+        assert!(!accounts.verify("alice", "battery staple"));
+        assert!(!accounts.verify("bob", "correct horse"));
+        // the file keeps hashes only
+        assert!(!fs::read_to_string(&path)?.contains("correct horse"));
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn failed_logins_should_back_off() -> Result<()> {
+        let accounts = Accounts::default();
+        let (ip, other) = ("10.0.0.1".parse()?, "10.0.0.2".parse()?);
+        for _ in 0..FREE_FAILURES {
+            accounts.failed(ip);
+        }
+        assert_eq!(accounts.backoff(ip), None);
+
+        accounts.failed(ip);
+        let wait = accounts.backoff(ip).unwrap();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_secs(1));
+        accounts.failed(ip);
+        assert!(accounts.backoff(ip).unwrap() > Duration::from_secs(1));
+        for _ in 0..20 {
+            accounts.failed(ip);
+        }
+        assert!(accounts.backoff(ip).unwrap() <= MAX_BACKOFF);
+        assert_eq!(accounts.backoff(other), None);
+
+        accounts.succeeded(ip);
+        assert_eq!(accounts.backoff(ip), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display},
+    net::IpAddr,
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use super::store::JsonFile;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum BanTarget {
+    // lowercased, like the username index
+    User(String),
+    Ip(IpAddr),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ban {
+    pub target: BanTarget,
+    // `None` bans forever
+    pub until: Option<DateTime<Utc>>,
+    pub by: String,
+}
+
+// active bans, saved to a JSON file on every change when a path is set
+#[derive(Debug, Default)]
+pub struct Bans {
+    file: Option<JsonFile>,
+    bans: Mutex<Vec<Ban>>,
+}
+
+impl BanTarget {
+    // an IP address, or else a username
+    pub fn parse(target: &str) -> Self {
+        match target.parse() {
+            Ok(ip) => BanTarget::Ip(ip),
+            Err(_) => BanTarget::User(target.to_lowercase()),
+        }
+    }
+}
+
+impl Display for BanTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BanTarget::User(name) => write!(f, "{}", name),
+            BanTarget::Ip(ip) => write!(f, "{}", ip),
+        }
+    }
+}
+
+impl Bans {
+    // load bans from `path`, a missing file means no bans yet
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let file = JsonFile::new(path, "ban file");
+        Ok(Self {
+            bans: Mutex::new(file.load()?),
+            file: Some(file),
+        })
+    }
+
+    // add or replace the ban of a target
+    pub fn add(&self, ban: Ban) -> Result<()> {
+        let mut bans = self.bans.lock().unwrap();
+        bans.retain(|b| b.target != ban.target);
+        bans.push(ban);
+        self.save(&bans)
+    }
+
+    pub fn is_banned(&self, target: &BanTarget) -> bool {
+        let now = Utc::now();
+        let mut bans = self.bans.lock().unwrap();
+        // expired bans are dropped lazily, the file catches up on the next change
+        bans.retain(|ban| ban.until.is_none_or(|until| until > now));
+        bans.iter().any(|ban| &ban.target == target)
+    }
+
+    pub fn is_banned_user(&self, username: &str) -> bool {
+        self.is_banned(&BanTarget::User(username.to_lowercase()))
+    }
+
+    pub fn is_banned_ip(&self, ip: IpAddr) -> bool {
+        self.is_banned(&BanTarget::Ip(ip))
+    }
+
+    fn save(&self, bans: &[Ban]) -> Result<()> {
+        match &self.file {
+            Some(file) => file.save(bans),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use std::fs;
+
+    #[test]
+    fn bans_should_expire_and_persist() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("bans-{}.json", nanoid::nanoid!()));
+        let bans = Bans::load(&path)?;
+        let ip = "10.0.0.1".parse()?;
+
+        bans.add(Ban {
+            target: BanTarget::parse("Mallory"),
+            until: None,
+            by: "admin".to_string(),
+        })?;
+        bans.add(Ban {
+            target: BanTarget::parse("10.0.0.1"),
+            until: Some(Utc::now() + Duration::hours(1)),
+            by: "admin".to_string(),
+        })?;
+        bans.add(Ban {
+            target: BanTarget::parse("eve"),
+            until: Some(Utc::now() - Duration::seconds(1)),
+            by: "admin".to_string(),
+        })?;
+
+        let bans = Bans::load(&path)?;
+        assert!(bans.is_banned_user("mallory"));
+        assert!(bans.is_banned_ip(ip));
+        assert!(!bans.is_banned_user("eve"));
+        assert!(!bans.is_banned_ip("10.0.0.2".parse()?));
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,366 @@
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use dashmap::DashMap;
+use http::{header, Request, Uri};
+use http_body_util::{BodyExt, Empty, Limited};
+use hyper_util::rt::TokioIo;
+use rand::Rng;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::net::{self, TcpStream};
+
+use super::{
+    command,
+    plugin::{Action, Context, Plugin},
+};
+
+const MAX_DICE: u32 = 100;
+const MAX_SIDES: u32 = 1000;
+
+// longest wait a reminder may be set for
+const MAX_REMINDER: Duration = Duration::from_secs(24 * 60 * 60);
+// reminders a user may have pending at once
+const MAX_REMINDERS: usize = 10;
+
+// bytes of a page read while looking for its title
+const MAX_PAGE: usize = 256 * 1024;
+const MAX_TITLE_LEN: usize = 120;
+
+// the bots `[chat.plugins] enabled` may list
+pub fn bot(name: &str) -> Result<Arc<dyn Plugin>> {
+    match name {
+        "dice" => Ok(Arc::new(Dice)),
+        "title" => Ok(Arc::new(Title)),
+        "reminder" => Ok(Arc::new(Reminder::default())),
+        _ => bail!("Unknown bot {}, expected dice, title or reminder", name),
+    }
+}
+
+// `/roll [count]d<sides>`, the roll is shown to the whole room
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Dice;
+
+// says the title of the first http:// page linked in a message, once it is fetched; pages on
+// private addresses are not fetched, links must not reach into the server's own network
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Title;
+
+// `/remind <duration> <text>`, told privately once the time is up
+#[derive(Debug, Default)]
+pub struct Reminder {
+    // lowercased username to when its pending reminders are due
+    pending: DashMap<String, Vec<Instant>>,
+}
+
+#[async_trait]
+impl Plugin for Dice {
+    fn name(&self) -> &str {
+        "dice"
+    }
+
+    fn commands(&self) -> &[&str] {
+        &["roll"]
+    }
+
+    async fn on_command(&self, ctx: &Context, _name: &str, args: &str) -> Result<Vec<Action>> {
+        let spec = if args.is_empty() { "1d6" } else { args };
+        let (count, sides) = parse_dice(spec)?;
+        let rolls = {
+            let mut rng = rand::thread_rng();
+            (0..count)
+                .map(|_| rng.gen_range(1..=sides))
+                .collect::<Vec<_>>()
+        };
+        let total = rolls.iter().sum::<u32>();
+        let text = match rolls.as_slice() {
+            [roll] => format!("{} rolled {}: {}", ctx.username, spec, roll),
+            rolls => {
+                let rolls = rolls.iter().map(u32::to_string).collect::<Vec<_>>();
+                format!(
+                    "{} rolled {}: {} = {}",
+                    ctx.username,
+                    spec,
+                    rolls.join(" + "),
+                    total
+                )
+            }
+        };
+        Ok(vec![Action::Broadcast(text)])
+    }
+}
+
+#[async_trait]
+impl Plugin for Title {
+    fn name(&self) -> &str {
+        "title"
+    }
+
+    async fn on_posted(&self, _ctx: &Context, content: &str) -> Result<Vec<Action>> {
+        let Some(url) = content
+            .split_whitespace()
+            .find(|word| word.starts_with("http://"))
+        else {
+            return Ok(Vec::new());
+        };
+        let uri = url.parse::<Uri>()?;
+        let addr = resolve(&uri).await?;
+        Ok(fetch_title(&uri, addr)
+            .await?
+            .map(Action::Broadcast)
+            .into_iter()
+            .collect())
+    }
+}
+
+#[async_trait]
+impl Plugin for Reminder {
+    fn name(&self) -> &str {
+        "reminder"
+    }
+
+    fn commands(&self) -> &[&str] {
+        &["remind"]
+    }
+
+    async fn on_command(&self, ctx: &Context, _name: &str, args: &str) -> Result<Vec<Action>> {
+        let usage = || anyhow!("Usage: /remind <duration> <text>, e.g. /remind 10m tea");
+        let (duration, text) = args.split_once(char::is_whitespace).ok_or_else(usage)?;
+        let delay = command::parse_duration(duration).map_err(|_| usage())?;
+        if delay > MAX_REMINDER {
+            bail!("Reminders are for up to {}h", MAX_REMINDER.as_secs() / 3600);
+        }
+
+        let now = Instant::now();
+        let mut pending = self.pending.entry(ctx.username.to_lowercase()).or_default();
+        pending.retain(|due| *due > now);
+        if pending.len() >= MAX_REMINDERS {
+            bail!("You have {} reminders pending already", MAX_REMINDERS);
+        }
+        pending.push(now + delay);
+
+        let reminder = Action::Reply(format!("Reminder: {}", text.trim()));
+        Ok(vec![
+            Action::Reply(format!("I will remind you in {}", duration)),
+            Action::After(delay, Box::new(reminder)),
+        ])
+    }
+}
+
+// `[count]d<sides>`
+fn parse_dice(spec: &str) -> Result<(u32, u32)> {
+    let invalid = || anyhow!("Usage: /roll [count]d<sides>, e.g. /roll 2d6");
+    let (count, sides) = spec.split_once('d').ok_or_else(invalid)?;
+    let count = match count {
+        "" => 1,
+        count => count.parse().map_err(|_| invalid())?,
+    };
+    let sides = sides.parse().map_err(|_| invalid())?;
+    if !(1..=MAX_DICE).contains(&count) || !(2..=MAX_SIDES).contains(&sides) {
+        bail!("Roll 1 to {} dice of 2 to {} sides", MAX_DICE, MAX_SIDES);
+    }
+    Ok((count, sides))
+}
+
+// the address to fetch `uri` from, only a public one; the page is fetched from this very
+// address, so the name cannot resolve to another one in between
+async fn resolve(uri: &Uri) -> Result<SocketAddr> {
+    let host = uri.host().ok_or_else(|| anyhow!("no host in {}", uri))?;
+    let addr = net::lookup_host((host, uri.port_u16().unwrap_or(80)))
+        .await?
+        .next()
+        .ok_or_else(|| anyhow!("no address for {}", host))?;
+    if !is_public(addr.ip()) {
+        bail!("{} is not a public address", addr.ip());
+    }
+    Ok(addr)
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            // 100.64.0.0/10 is carrier-grade NAT
+            let shared = a == 100 && (64..128).contains(&b);
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || shared)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+// plain HTTP only, `None` when the page has no title
+async fn fetch_title(uri: &Uri, addr: SocketAddr) -> Result<Option<String>> {
+    let host = uri.host().ok_or_else(|| anyhow!("no host in {}", uri))?;
+    let stream = TcpStream::connect(addr).await?;
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(conn);
+
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+    let req = Request::get(path)
+        .header(header::HOST, host)
+        .body(Empty::<Bytes>::new())?;
+    let res = sender.send_request(req).await?;
+    if !res.status().is_success() {
+        return Ok(None);
+    }
+    let body = Limited::new(res.into_body(), MAX_PAGE)
+        .collect()
+        .await
+        .map_err(|e| anyhow!(e))?
+        .to_bytes();
+    Ok(title(&String::from_utf8_lossy(&body)))
+}
+
+// the text of the first <title> element, whitespace collapsed; control characters are dropped,
+// the page is anyone's and the title goes out to plain clients' terminals
+fn title(html: &str) -> Option<String> {
+    let lower = html.to_ascii_lowercase();
+    let open = lower.find("<title")?;
+    let start = open + lower[open..].find('>')? + 1;
+    let end = start + lower[start..].find("</title")?;
+    let title = html[start..end]
+        .split_whitespace()
+        .map(|word| word.chars().filter(|c| !c.is_control()).collect::<String>())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>();
+    if title.is_empty() {
+        return None;
+    }
+    Some(title.join(" ").chars().take(MAX_TITLE_LEN).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{response::Html, routing::get, Router};
+    use tokio::net::TcpListener;
+
+    fn ctx() -> Context {
+        Context::new("alice", Some("lobby".to_string()))
+    }
+
+    #[tokio::test]
+    async fn dice_should_roll_within_range() -> Result<()> {
+        assert_eq!(parse_dice("d20")?, (1, 20));
+        assert_eq!(parse_dice("3d6")?, (3, 6));
+        assert!(parse_dice("0d6").is_err());
+        assert!(parse_dice("2d1").is_err());
+        assert!(parse_dice("2x6").is_err());
+
+        let actions = Dice.on_command(&ctx(), "roll", "").await?;
+        let [Action::Broadcast(text)] = actions.as_slice() else {
+            panic!("expected a broadcast, got {:?}", actions);
+        };
+        let roll = text.strip_prefix("alice rolled 1d6: ").unwrap();
+        assert!((1..=6).contains(&roll.parse::<u32>()?));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn title_should_come_from_the_linked_page() -> Result<()> {
+        let app = Router::new()
+            .route(
+                "/page",
+                get(|| async {
+                    Html("<html><head><TITLE>\n  A   stub page\n</TITLE></head></html>")
+                }),
+            )
+            .route(
+                "/escape",
+                get(|| async { Html("<title>\x1b[2JCleared \x07 screen</title>") }),
+            )
+            .route("/bare", get(|| async { Html("<p>no title here</p>") }));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let uri = format!("http://{}/page", addr).parse::<Uri>()?;
+        let title = fetch_title(&uri, addr).await?;
+        assert_eq!(title.as_deref(), Some("A stub page"));
+        let uri = format!("http://{}/escape", addr).parse::<Uri>()?;
+        let title = fetch_title(&uri, addr).await?;
+        assert_eq!(title.as_deref(), Some("[2JCleared screen"));
+        let uri = format!("http://{}/bare", addr).parse::<Uri>()?;
+        assert_eq!(fetch_title(&uri, addr).await?, None);
+
+        // the bot itself refuses to fetch from this machine
+        let content = format!("look at http://{}/page please", addr);
+        let err = Title.on_posted(&ctx(), &content).await.unwrap_err();
+        assert_eq!(err.to_string(), "127.0.0.1 is not a public address");
+        assert!(Title.on_posted(&ctx(), "no link").await?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn only_public_addresses_should_be_fetched() {
+        for ip in ["93.184.215.14", "2606:2800:21f:cb07:6820:80da:af6b:8b2c"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fe80::1",
+            "fd00::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn reminder_should_reply_later() -> Result<()> {
+        let reminder = Reminder::default();
+        let actions = reminder.on_command(&ctx(), "remind", "10m  tea").await?;
+        assert_eq!(
+            actions,
+            [
+                Action::Reply("I will remind you in 10m".to_string()),
+                Action::After(
+                    Duration::from_secs(600),
+                    Box::new(Action::Reply("Reminder: tea".to_string()))
+                ),
+            ]
+        );
+        assert!(reminder.on_command(&ctx(), "remind", "tea").await.is_err());
+        assert!(reminder
+            .on_command(&ctx(), "remind", "2d tea")
+            .await
+            .is_err());
+
+        for _ in 1..MAX_REMINDERS {
+            reminder.on_command(&ctx(), "remind", "1h tea").await?;
+        }
+        let err = reminder
+            .on_command(&ctx(), "remind", "1h tea")
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "You have 10 reminders pending already");
+        let bob = Context::new("bob", None);
+        assert!(reminder.on_command(&bob, "remind", "1h tea").await.is_ok());
+        assert!(bot("weather").is_err());
+        Ok(())
+    }
+}
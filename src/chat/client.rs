@@ -0,0 +1,470 @@
+use anyhow::{anyhow, Result};
+use futures::{SinkExt, Stream, StreamExt};
+use nu_ansi_term::{Color, Style};
+use std::{iter, time::Duration};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    time,
+};
+use tokio_util::codec::{Framed, LinesCodec};
+
+use super::{line_too_long, Command, Entry, Message, Request, MAX_LINE_LENGTH};
+
+// senders get one of these, errors keep red to themselves
+const PALETTE: [Color; 6] = [
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Purple,
+    Color::Cyan,
+    Color::LightGreen,
+];
+
+type Connection = Framed<TcpStream, LinesCodec>;
+
+// delays between reconnects, doubling up to `max`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+// terminal client speaking the JSON protocol, it reconnects and resumes its session until /quit
+pub struct Client {
+    addr: String,
+    username: String,
+    password: Option<String>,
+    color: bool,
+    backoff: Backoff,
+    // from the server, to take the session back after reconnecting
+    token: Option<String>,
+}
+
+enum End {
+    Quit,
+    // the server closed the session on purpose, e.g. a kick
+    Closed,
+    Lost(String),
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Backoff {
+    pub fn delays(&self) -> impl Iterator<Item = Duration> {
+        let max = self.max;
+        iter::successors(Some(self.initial.min(max)), move |delay| {
+            Some(delay.saturating_mul(2).min(max))
+        })
+    }
+}
+
+impl Client {
+    pub fn new(addr: impl Into<String>, username: impl Into<String>) -> Self {
+        Self {
+            addr: addr.into(),
+            username: username.into(),
+            password: None,
+            color: true,
+            backoff: Backoff::default(),
+            token: None,
+        }
+    }
+
+    pub fn with_password(mut self, password: impl Into<String>) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    // chat with lines from `input` until it ends or says /quit, printing to `out`
+    pub async fn run<I, W>(mut self, mut input: I, mut out: W) -> Result<()>
+    where
+        I: Stream<Item = Result<String>> + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut delays = self.backoff.delays();
+        loop {
+            let end = match self.connect(&mut out).await {
+                Ok(conn) => {
+                    delays = self.backoff.delays();
+                    self.session(conn, &mut input, &mut out).await?
+                }
+                Err(e) => End::Lost(format!("{:#}", e)),
+            };
+            let reason = match end {
+                End::Quit | End::Closed => return Ok(()),
+                End::Lost(reason) => reason,
+            };
+
+            let delay = delays.next().unwrap_or(self.backoff.max);
+            let notice = format!("Not connected ({}), retrying in {:?}", reason, delay);
+            self.print(&mut out, &Message::error(notice)).await?;
+            let sleep = time::sleep(delay);
+            tokio::pin!(sleep);
+            loop {
+                tokio::select! {
+                    _ = &mut sleep => break,
+                    line = input.next() => {
+                        let Some(line) = line else {
+                            return Ok(());
+                        };
+                        if line?.trim() == "/quit" {
+                            return Ok(());
+                        }
+                        let msg = Message::error("Not connected, nothing was sent");
+                        self.print(&mut out, &msg).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    // switch to JSON, then resume the previous session or log in
+    async fn connect<W: AsyncWrite + Unpin>(&mut self, out: &mut W) -> Result<Connection> {
+        let stream = TcpStream::connect(&self.addr).await?;
+        let mut conn = Framed::new(stream, LinesCodec::new());
+
+        // the plain username prompt comes first, the protocol switch is answered in JSON
+        conn.send("PROTO json".to_string()).await?;
+        loop {
+            if let Ok(entry) = serde_json::from_str::<Entry>(&next(&mut conn).await?) {
+                if let Message::Error { text } = entry.message {
+                    return Err(anyhow!(text));
+                }
+                break;
+            }
+        }
+        // then the prompt again
+        next(&mut conn).await?;
+
+        if let Some(token) = self.token.take() {
+            send(&mut conn, &Request::Resume { token }).await?;
+            match parse(&next(&mut conn).await?) {
+                // the session expired, log in again after the next prompt
+                Message::Error { .. } => {
+                    next(&mut conn).await?;
+                }
+                msg => {
+                    self.show(out, msg).await?;
+                    return Ok(conn);
+                }
+            }
+        }
+
+        let login = Request::Login {
+            username: self.username.clone(),
+            password: self.password.clone(),
+        };
+        send(&mut conn, &login).await?;
+        Ok(conn)
+    }
+
+    async fn session<I, W>(
+        &mut self,
+        mut conn: Connection,
+        input: &mut I,
+        out: &mut W,
+    ) -> Result<End>
+    where
+        I: Stream<Item = Result<String>> + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        // an error right before the server hangs up means we were thrown out
+        let mut last_error = false;
+        loop {
+            tokio::select! {
+                line = conn.next() => {
+                    let line = match line {
+                        Some(Ok(line)) => line,
+                        Some(Err(e)) => return Ok(End::Lost(e.to_string())),
+                        None if last_error => return Ok(End::Closed),
+                        None => return Ok(End::Lost("closed by the server".to_string())),
+                    };
+                    let msg = parse(&line);
+                    last_error = matches!(msg, Message::Error { .. });
+                    if msg == Message::Ping {
+                        if let Err(e) = send(&mut conn, &Request::Pong).await {
+                            return Ok(End::Lost(e.to_string()));
+                        }
+                        continue;
+                    }
+                    self.show(out, msg).await?;
+                }
+                line = input.next() => {
+                    let line = match line {
+                        Some(line) => line?,
+                        None => return Ok(End::Quit),
+                    };
+                    let line = line.trim();
+                    if line.is_empty() {
+                        continue;
+                    }
+                    if line == "/quit" {
+                        return Ok(End::Quit);
+                    }
+                    match request(line) {
+                        Ok(request) => {
+                            if let Err(e) = send(&mut conn, &request).await {
+                                return Ok(End::Lost(e.to_string()));
+                            }
+                        }
+                        Err(e) => self.print(out, &Message::error(e.to_string())).await?,
+                    }
+                }
+            }
+        }
+    }
+
+    // the resume token is kept rather than shown
+    async fn show<W: AsyncWrite + Unpin>(&mut self, out: &mut W, msg: Message) -> Result<()> {
+        match msg {
+            Message::Resume { token } => {
+                self.token = Some(token);
+                Ok(())
+            }
+            msg => self.print(out, &msg).await,
+        }
+    }
+
+    async fn print<W: AsyncWrite + Unpin>(&self, out: &mut W, msg: &Message) -> Result<()> {
+        let msg = strip(msg);
+        let text = if self.color {
+            render(&msg)
+        } else {
+            msg.to_string()
+        };
+        out.write_all(format!("{}\n", text).as_bytes()).await?;
+        out.flush().await?;
+        Ok(())
+    }
+}
+
+// slash commands are checked here first, so typos don't need a round trip
+fn request(line: &str) -> Result<Request> {
+    let request = match Command::parse(line) {
+        Some(Err(e)) => return Err(e),
+        Some(Ok(_)) => Request::Command {
+            command: line.to_string(),
+        },
+        None => Request::Chat {
+            content: line.to_string(),
+        },
+    };
+    if serde_json::to_string(&request)?.len() > MAX_LINE_LENGTH {
+        return Err(line_too_long());
+    }
+    Ok(request)
+}
+
+// server lines are JSON entries, anything else is shown as is
+fn parse(line: &str) -> Message {
+    serde_json::from_str::<Entry>(line)
+        .map_or_else(|_| Message::system(line), |entry| entry.message)
+}
+
+async fn next(conn: &mut Connection) -> Result<String> {
+    match conn.next().await {
+        Some(line) => Ok(line?),
+        None => Err(anyhow!("closed by the server")),
+    }
+}
+
+async fn send(conn: &mut Connection, request: &Request) -> Result<()> {
+    conn.send(serde_json::to_string(request)?).await?;
+    Ok(())
+}
+
+// whatever the server sends, escape sequences or line breaks in it must not reach the terminal,
+// where they could move the cursor, retitle the window or fake other lines
+fn strip(msg: &Message) -> Message {
+    let clean = |text: &String| {
+        text.chars()
+            .filter(|c| !c.is_control() || *c == '\t')
+            .collect::<String>()
+    };
+    match msg {
+        Message::UserJoined { room, username } => Message::joined(clean(room), clean(username)),
+        Message::UserLeft { room, username } => Message::left(clean(room), clean(username)),
+        Message::Chat {
+            room,
+            sender,
+            content,
+        } => Message::chat(clean(room), clean(sender), clean(content)),
+        Message::Renamed { room, from, to } => {
+            Message::renamed(clean(room), clean(from), clean(to))
+        }
+        Message::Private { sender, content } => Message::private(clean(sender), clean(content)),
+        Message::System { text } => Message::system(clean(text)),
+        Message::Error { text } => Message::error(clean(text)),
+        Message::Ping => Message::Ping,
+        Message::Resume { token } => Message::resume(clean(token)),
+    }
+}
+
+// the plain text of `Message`, with each sender in a color of their own
+fn render(msg: &Message) -> String {
+    let dimmed = Style::new().dimmed();
+    match msg {
+        Message::Chat {
+            room,
+            sender,
+            content,
+        } => format!(
+            "{} {}: {}",
+            dimmed.paint(format!("[{}]", room)),
+            sender_color(sender).bold().paint(sender),
+            content
+        ),
+        Message::Private { sender, content } => format!(
+            "{} {}: {}",
+            Color::Purple.paint("[pm]"),
+            sender_color(sender).bold().paint(sender),
+            content
+        ),
+        Message::Error { .. } => Color::Red.paint(msg.to_string()).to_string(),
+        _ => dimmed.paint(msg.to_string()).to_string(),
+    }
+}
+
+fn sender_color(sender: &str) -> Color {
+    let hash = sender.bytes().fold(0usize, |hash, b| {
+        hash.wrapping_mul(31).wrapping_add(b as usize)
+    });
+    PALETTE[hash % PALETTE.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{accept, State};
+    use futures::{channel::mpsc, future};
+    use std::sync::Arc;
+    use tokio::{
+        io::{self, DuplexStream},
+        net::TcpListener,
+    };
+    use tokio_util::codec::FramedRead;
+
+    async fn listen(addr: &str) -> Result<(Arc<State>, String)> {
+        let state = Arc::new(State::default());
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?.to_string();
+        tokio::spawn(accept(listener, state.clone(), |s| future::ready(Ok(s))));
+        Ok((state, addr))
+    }
+
+    async fn wait_for(out: &mut FramedRead<DuplexStream, LinesCodec>, expected: &str) {
+        while let Some(Ok(line)) = out.next().await {
+            if line == expected {
+                return;
+            }
+        }
+        panic!("client stopped before {:?}", expected);
+    }
+
+    #[test]
+    fn backoff_should_double_up_to_max() {
+        let delays: Vec<_> = Backoff::default().delays().take(7).collect();
+        assert_eq!(
+            delays,
+            [1, 2, 4, 8, 16, 30, 30].map(Duration::from_secs).to_vec()
+        );
+    }
+
+    #[test]
+    fn render_should_color_senders() {
+        let line = render(&Message::chat("lobby", "alice", "hi"));
+        assert!(line.contains(&sender_color("alice").bold().paint("alice").to_string()));
+        assert!(line.ends_with(": hi"));
+        assert_eq!(sender_color("alice"), sender_color("alice"));
+        assert!(render(&Message::error("nope")).starts_with("\x1b[31m"));
+    }
+
+    #[test]
+    fn strip_should_drop_control_characters() {
+        let msg = Message::chat("lobby", "mal\x1b[2Jlory", "hi\x1b]0;pwned\x07\n* fake\tok");
+        assert_eq!(
+            strip(&msg),
+            Message::chat("lobby", "mal[2Jlory", "hi]0;pwned* fake\tok")
+        );
+        assert_eq!(
+            strip(&Message::system("line\r\nbreak")),
+            Message::system("linebreak")
+        );
+    }
+
+    #[test]
+    fn request_should_check_commands() {
+        assert_eq!(
+            request("/join rust").unwrap(),
+            Request::Command {
+                command: "/join rust".to_string()
+            }
+        );
+        assert_eq!(
+            request("hi").unwrap(),
+            Request::Chat {
+                content: "hi".to_string()
+            }
+        );
+        // unknown to the client, a server plugin may have it
+        assert_eq!(
+            request("/roll 2d6").unwrap(),
+            Request::Command {
+                command: "/roll 2d6".to_string()
+            }
+        );
+        assert!(request("/join").is_err());
+        assert!(request(&"a".repeat(MAX_LINE_LENGTH)).is_err());
+    }
+
+    #[tokio::test]
+    async fn client_should_reconnect_after_server_restart() -> Result<()> {
+        let (state, addr) = listen("127.0.0.1:0").await?;
+        let (input_tx, input_rx) = mpsc::unbounded();
+        let (client_out, out) = io::duplex(4096);
+        let mut out = FramedRead::new(out, LinesCodec::new());
+        let backoff = Backoff {
+            initial: Duration::from_millis(10),
+            max: Duration::from_millis(50),
+        };
+        let client = Client::new(&addr, "alice")
+            .with_color(false)
+            .with_backoff(backoff);
+        let running = tokio::spawn(client.run(input_rx.map(Ok), client_out));
+
+        wait_for(&mut out, "* You joined lobby").await;
+        input_tx.unbounded_send("/dance".to_string())?;
+        wait_for(&mut out, "* Unknown command: /dance").await;
+        input_tx.unbounded_send("/who".to_string())?;
+        wait_for(&mut out, "* In lobby: alice").await;
+
+        // a new server knows nothing of the old session, so the client logs in again
+        state.shutdown(Duration::from_secs(1)).await;
+        wait_for(&mut out, "* Server shutting down").await;
+        let (state, _) = listen(&addr).await?;
+        wait_for(&mut out, "* You joined lobby").await;
+        assert!(state.lookup("alice").is_some());
+
+        input_tx.unbounded_send("/quit".to_string())?;
+        running.await??;
+        Ok(())
+    }
+}
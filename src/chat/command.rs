@@ -0,0 +1,281 @@
+use anyhow::{anyhow, Result};
+use std::time::Duration;
+
+const MAX_ROOM_LEN: usize = 32;
+const MAX_USERNAME_LEN: usize = 16;
+
+// messages replayed by a bare /history, and on joining a room
+pub const DEFAULT_REPLAY: usize = 20;
+
+// how long a bare /mute lasts
+pub const DEFAULT_MUTE: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Join(String),
+    Leave,
+    Rooms,
+    Who,
+    Nick(String),
+    Msg {
+        to: String,
+        content: String,
+    },
+    History(usize),
+    // claim the current username with a password
+    Register(String),
+    // moderation, all but /admin need the admin role
+    Admin(String),
+    Kick(String),
+    Ban {
+        target: String,
+        duration: Option<Duration>,
+    },
+    Mute {
+        user: String,
+        duration: Option<Duration>,
+    },
+    Announce(String),
+    // none of the above, for a plugin to handle
+    Plugin {
+        name: String,
+        args: String,
+    },
+}
+
+impl Command {
+    pub fn needs_admin(&self) -> bool {
+        matches!(
+            self,
+            Command::Kick(_) | Command::Ban { .. } | Command::Mute { .. } | Command::Announce(_)
+        )
+    }
+
+    // returns `None` when the line is a plain chat message rather than a command
+    pub fn parse(line: &str) -> Option<Result<Self>> {
+        let line = line.trim();
+        let rest = line.strip_prefix('/')?;
+        let (name, args) = match rest.split_once(char::is_whitespace) {
+            Some((name, args)) => (name, args.trim()),
+            None => (rest, ""),
+        };
+
+        let cmd = match name {
+            "join" => validate_room(args).map(|room| Command::Join(room.to_string())),
+            "leave" => Ok(Command::Leave),
+            "rooms" => Ok(Command::Rooms),
+            "who" => Ok(Command::Who),
+            "nick" => validate_username(args).map(|name| Command::Nick(name.to_string())),
+            "msg" => match args.split_once(char::is_whitespace) {
+                Some((to, content)) if !content.trim().is_empty() => Ok(Command::Msg {
+                    to: to.to_string(),
+                    content: content.trim().to_string(),
+                }),
+                _ => Err(anyhow!("Usage: /msg <user> <text>")),
+            },
+            "history" if args.is_empty() => Ok(Command::History(DEFAULT_REPLAY)),
+            "history" => args
+                .parse()
+                .map(Command::History)
+                .map_err(|_| anyhow!("Usage: /history [count]")),
+            "register" if !args.is_empty() => Ok(Command::Register(args.to_string())),
+            "register" => Err(anyhow!("Usage: /register <password>")),
+            "admin" if !args.is_empty() => Ok(Command::Admin(args.to_string())),
+            "admin" => Err(anyhow!("Usage: /admin <secret>")),
+            "kick" => validate_username(args)
+                .map(|user| Command::Kick(user.to_string()))
+                .map_err(|_| anyhow!("Usage: /kick <user>")),
+            "ban" => parse_target(args)
+                .map(|(target, duration)| Command::Ban { target, duration })
+                .map_err(|e| anyhow!("Usage: /ban <user|ip> [duration], {}", e)),
+            "mute" => parse_target(args)
+                .map(|(user, duration)| Command::Mute { user, duration })
+                .map_err(|e| anyhow!("Usage: /mute <user> [duration], {}", e)),
+            "announce" if !args.is_empty() => Ok(Command::Announce(args.to_string())),
+            "announce" => Err(anyhow!("Usage: /announce <text>")),
+            _ if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric()) => {
+                Ok(Command::Plugin {
+                    name: name.to_string(),
+                    args: args.to_string(),
+                })
+            }
+            _ => Err(anyhow!("Unknown command: /{}", name)),
+        };
+        Some(cmd)
+    }
+}
+
+pub fn validate_room(room: &str) -> Result<&str> {
+    if room.is_empty() {
+        return Err(anyhow!("Usage: /join <room>"));
+    }
+
+    let valid = room.len() <= MAX_ROOM_LEN
+        && room
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(anyhow!(
+            "Room names are up to {} letters, digits, '-' or '_'",
+            MAX_ROOM_LEN
+        ));
+    }
+
+    Ok(room)
+}
+
+// `<target> [duration]`
+fn parse_target(args: &str) -> Result<(String, Option<Duration>)> {
+    let mut parts = args.split_whitespace();
+    let target = parts.next().ok_or_else(|| anyhow!("missing target"))?;
+    let duration = parts.next().map(parse_duration).transpose()?;
+    if parts.next().is_some() {
+        return Err(anyhow!("too many arguments"));
+    }
+    Ok((target.to_string(), duration))
+}
+
+// a count with a unit: 30s, 10m, 2h or 7d
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let invalid = || anyhow!("invalid duration {}, use e.g. 30s, 10m, 2h or 7d", s);
+    let unit = s.chars().last().ok_or_else(invalid)?;
+    let count = s[..s.len() - unit.len_utf8()]
+        .parse::<u64>()
+        .map_err(|_| invalid())?;
+    let secs = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    count
+        .checked_mul(secs)
+        .map(Duration::from_secs)
+        .ok_or_else(invalid)
+}
+
+pub fn validate_username(name: &str) -> Result<&str> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_USERNAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(anyhow!(
+            "Usernames are 1 to {} letters, digits, '-' or '_'",
+            MAX_USERNAME_LEN
+        ));
+    }
+
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_should_parse() {
+        assert!(Command::parse("hello /join").is_none());
+        assert_eq!(
+            Command::parse("/join rust").unwrap().unwrap(),
+            Command::Join("rust".to_string())
+        );
+        assert_eq!(Command::parse(" /leave ").unwrap().unwrap(), Command::Leave);
+        assert_eq!(Command::parse("/rooms").unwrap().unwrap(), Command::Rooms);
+        assert_eq!(Command::parse("/who").unwrap().unwrap(), Command::Who);
+        assert_eq!(
+            Command::parse("/nick bob_2").unwrap().unwrap(),
+            Command::Nick("bob_2".to_string())
+        );
+        assert_eq!(
+            Command::parse("/msg bob hello  there ").unwrap().unwrap(),
+            Command::Msg {
+                to: "bob".to_string(),
+                content: "hello  there".to_string()
+            }
+        );
+        assert_eq!(
+            Command::parse("/roll  2d6").unwrap().unwrap(),
+            Command::Plugin {
+                name: "roll".to_string(),
+                args: "2d6".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn command_should_reject_invalid_input() {
+        assert!(Command::parse("/join").unwrap().is_err());
+        assert!(Command::parse("/join a b").unwrap().is_err());
+        assert!(Command::parse(&format!("/join {}", "a".repeat(33)))
+            .unwrap()
+            .is_err());
+        assert!(Command::parse("/").unwrap().is_err());
+        assert!(Command::parse("/da.nce").unwrap().is_err());
+        assert!(Command::parse("/nick").unwrap().is_err());
+        assert!(Command::parse("/nick bad name").unwrap().is_err());
+        assert!(Command::parse("/msg bob").unwrap().is_err());
+        assert!(Command::parse("/msg bob   ").unwrap().is_err());
+        assert!(Command::parse("/history -1").unwrap().is_err());
+    }
+
+    #[test]
+    fn history_should_default_count() {
+        assert_eq!(
+            Command::parse("/history").unwrap().unwrap(),
+            Command::History(DEFAULT_REPLAY)
+        );
+        assert_eq!(
+            Command::parse("/history 50").unwrap().unwrap(),
+            Command::History(50)
+        );
+    }
+
+    #[test]
+    fn moderation_commands_should_parse() {
+        assert_eq!(
+            Command::parse("/ban 10.0.0.1 2h").unwrap().unwrap(),
+            Command::Ban {
+                target: "10.0.0.1".to_string(),
+                duration: Some(Duration::from_secs(7200))
+            }
+        );
+        assert_eq!(
+            Command::parse("/mute bob").unwrap().unwrap(),
+            Command::Mute {
+                user: "bob".to_string(),
+                duration: None
+            }
+        );
+        assert_eq!(
+            Command::parse("/announce back in 5").unwrap().unwrap(),
+            Command::Announce("back in 5".to_string())
+        );
+        assert!(Command::parse("/ban").unwrap().is_err());
+        assert!(Command::parse("/ban bob 2x").unwrap().is_err());
+        assert!(Command::parse("/kick").unwrap().is_err());
+        assert!(Command::parse("/admin").unwrap().is_err());
+    }
+
+    #[test]
+    fn duration_should_parse_units() {
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("10m").unwrap(), Duration::from_secs(600));
+        assert_eq!(parse_duration("7d").unwrap(), Duration::from_secs(604800));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("-1h").is_err());
+        assert!(parse_duration("99999999999999999d").is_err());
+    }
+
+    #[test]
+    fn username_should_be_validated() {
+        assert!(validate_username("alice-01_").is_ok());
+        assert!(validate_username("").is_err());
+        assert!(validate_username("  ").is_err());
+        assert!(validate_username("ünicode").is_err());
+        assert!(validate_username(&"a".repeat(17)).is_err());
+    }
+}
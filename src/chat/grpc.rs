@@ -0,0 +1,274 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use futures::{channel::mpsc, future, stream, SinkExt, Stream, StreamExt, TryStreamExt};
+use prost_types::Timestamp;
+use std::{pin::Pin, sync::Arc};
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::{transport::Server, Response, Status, Streaming};
+use tracing::{info, warn};
+
+use super::{
+    handle_session, line_too_long, Closed, Entry, Message, Protocol, Request, State,
+    MAX_LINE_LENGTH,
+};
+use crate::pb::{
+    chat_event::Event,
+    chat_service_server::{ChatService, ChatServiceServer},
+    ChatEvent, ChatLine, ChatMember, ChatRename,
+};
+
+// events queued for a gRPC client before the session waits on it
+const OUTGOING: usize = 32;
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<ChatEvent, Status>> + Send>>;
+
+// gRPC clients talk to the same `State` as TCP clients, one Chat call per session
+#[derive(Debug, Clone)]
+pub struct ChatGrpc {
+    state: Arc<State>,
+}
+
+impl ChatGrpc {
+    pub fn new(state: Arc<State>) -> Self {
+        Self { state }
+    }
+
+    pub fn into_service(self) -> ChatServiceServer<Self> {
+        ChatServiceServer::new(self)
+    }
+}
+
+pub async fn serve_grpc(addr: &str, state: Arc<State>) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Chat gRPC service listening on {}", addr);
+    serve_on(listener, state).await
+}
+
+async fn serve_on(listener: TcpListener, state: Arc<State>) -> Result<()> {
+    let service = ChatGrpc::new(state.clone()).into_service();
+    Server::builder()
+        .add_service(service)
+        .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async move {
+            state.closing().await
+        })
+        .await?;
+    Ok(())
+}
+
+#[tonic::async_trait]
+impl ChatService for ChatGrpc {
+    type ChatStream = EventStream;
+
+    async fn chat(
+        &self,
+        req: tonic::Request<Streaming<ChatEvent>>,
+    ) -> Result<Response<Self::ChatStream>, Status> {
+        let addr = req
+            .remote_addr()
+            .ok_or_else(|| Status::failed_precondition("Client address unknown"))?;
+        if self.state.bans().is_banned_ip(addr.ip()) {
+            info!("Rejected banned gRPC client {}", addr);
+            return Err(Status::permission_denied("You are banned"));
+        }
+        // calls over one connection share its address
+        let Some(reservation) = self.state.reserve(addr) else {
+            return Err(Status::already_exists(
+                "This connection already has a chat session",
+            ));
+        };
+        info!("gRPC client {} connected", addr);
+
+        // ending the request stream is a goodbye, unlike an error, which holds the session
+        // for a resume
+        let incoming = req
+            .into_inner()
+            .map_err(anyhow::Error::from)
+            .and_then(|event| future::ready(request(event)))
+            .chain(stream::once(future::ready(Err(Closed.into()))));
+        let (tx, rx) = mpsc::channel::<String>(OUTGOING);
+        let sink = tx.sink_map_err(anyhow::Error::from);
+
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            let session = handle_session(
+                state,
+                reservation,
+                Protocol::Json,
+                Box::pin(sink),
+                incoming.boxed(),
+            );
+            if let Err(e) = session.await {
+                warn!("Failed to handle client: {}, {}", addr, e);
+            }
+        });
+
+        let outgoing = rx.filter_map(move |line| {
+            future::ready(match event(&line) {
+                Ok(event) => Some(Ok(event)),
+                Err(e) => {
+                    warn!("Failed to convert {:?} for {}: {}", line, addr, e);
+                    None
+                }
+            })
+        });
+        Ok(Response::new(Box::pin(outgoing)))
+    }
+}
+
+// a client event as the JSON request line the session reads; the session decodes it like any
+// JSON client's, so content with line breaks is refused there
+fn request(event: ChatEvent) -> Result<String> {
+    let request = match event.event {
+        Some(Event::Login(login)) => Request::Login {
+            username: login.username,
+            password: Some(login.password).filter(|password| !password.is_empty()),
+        },
+        Some(Event::Send(command)) if command.trim_start().starts_with('/') => {
+            Request::Command { command }
+        }
+        Some(Event::Send(content)) => Request::Chat { content },
+        Some(Event::Resume(token)) => Request::Resume { token },
+        Some(Event::Pong(_)) => Request::Pong,
+        _ => return Err(anyhow!("Clients send login, send, resume or pong events")),
+    };
+    let line = serde_json::to_string(&request)?;
+    if line.len() > MAX_LINE_LENGTH {
+        return Err(line_too_long());
+    }
+    Ok(line)
+}
+
+// a JSON entry the session wrote as an event
+fn event(line: &str) -> Result<ChatEvent> {
+    let entry = serde_json::from_str::<Entry>(line)?;
+    let event = match entry.message {
+        Message::UserJoined { room, username } => Event::Joined(ChatMember { room, username }),
+        Message::UserLeft { room, username } => Event::Left(ChatMember { room, username }),
+        Message::Chat {
+            room,
+            sender,
+            content,
+        } => Event::Chat(ChatLine {
+            room,
+            sender,
+            content,
+        }),
+        Message::Renamed { room, from, to } => Event::Renamed(ChatRename { room, from, to }),
+        Message::Private { sender, content } => Event::Private(ChatLine {
+            room: String::new(),
+            sender,
+            content,
+        }),
+        Message::System { text } => Event::System(text),
+        Message::Error { text } => Event::Error(text),
+        Message::Ping => Event::Ping(true),
+        Message::Resume { token } => Event::Resume(token),
+    };
+    Ok(ChatEvent {
+        at: Some(timestamp(entry.at)),
+        event: Some(event),
+    })
+}
+
+fn timestamp(at: DateTime<Utc>) -> Timestamp {
+    Timestamp {
+        seconds: at.timestamp(),
+        nanos: at.timestamp_subsec_nanos() as i32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chat::handle_client,
+        pb::{chat_service_client::ChatServiceClient, ChatLogin},
+    };
+    use tokio::net::TcpStream;
+    use tokio_util::codec::{Framed, LinesCodec};
+
+    fn send(event: Event) -> ChatEvent {
+        ChatEvent {
+            at: None,
+            event: Some(event),
+        }
+    }
+
+    // skip events until one matches
+    async fn wait_for(events: &mut Streaming<ChatEvent>, expected: &Event) -> Result<()> {
+        while let Some(event) = events.message().await? {
+            if event.event.as_ref() == Some(expected) {
+                return Ok(());
+            }
+        }
+        Err(anyhow!("stream ended before {:?}", expected))
+    }
+
+    #[tokio::test]
+    async fn grpc_and_tcp_clients_should_share_rooms() -> Result<()> {
+        let state = Arc::new(State::default());
+
+        let tcp = TcpListener::bind("127.0.0.1:0").await?;
+        let tcp_addr = tcp.local_addr()?;
+        let tcp_state = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, addr)) = tcp.accept().await {
+                tokio::spawn(handle_client(tcp_state.clone(), addr, stream));
+            }
+        });
+        let grpc = TcpListener::bind("127.0.0.1:0").await?;
+        let grpc_addr = grpc.local_addr()?;
+        tokio::spawn(serve_on(grpc, state.clone()));
+
+        let mut nc = Framed::new(TcpStream::connect(tcp_addr).await?, LinesCodec::new());
+        nc.send("nc").await?;
+
+        let mut client = ChatServiceClient::connect(format!("http://{}", grpc_addr)).await?;
+        let (mut tx, rx) = mpsc::unbounded();
+        let mut events = client.chat(rx).await?.into_inner();
+        let login = ChatLogin {
+            username: "grpc".to_string(),
+            password: String::new(),
+        };
+        tx.send(send(Event::Login(login))).await?;
+        wait_for(&mut events, &Event::System("You joined lobby".to_string())).await?;
+
+        // one session per connection
+        let (_second_tx, second_rx) = mpsc::unbounded::<ChatEvent>();
+        let status = client.chat(second_rx).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::AlreadyExists);
+
+        let mut nc_lines = Vec::new();
+        while nc_lines.last().map(String::as_str) != Some("[lobby] grpc joined the room") {
+            nc_lines.push(nc.next().await.unwrap()?);
+        }
+        nc.send("hi grpc").await?;
+        let chat = ChatLine {
+            room: "lobby".to_string(),
+            sender: "nc".to_string(),
+            content: "hi grpc".to_string(),
+        };
+        wait_for(&mut events, &Event::Chat(chat)).await?;
+
+        tx.send(send(Event::Send("hi nc".to_string()))).await?;
+        assert_eq!(nc.next().await.unwrap()?, "[lobby] grpc: hi nc");
+        // a forged line for plain clients never goes out
+        let forged = "hi\n[lobby] nc: bye".to_string();
+        tx.send(send(Event::Send(forged))).await?;
+        let error = "Control characters are not allowed".to_string();
+        wait_for(&mut events, &Event::Error(error)).await?;
+        tx.send(send(Event::Send("/who".to_string()))).await?;
+        wait_for(
+            &mut events,
+            &Event::System("In lobby: grpc, nc".to_string()),
+        )
+        .await?;
+
+        // closing the request stream leaves at once, without waiting for a resume
+        drop(tx);
+        assert_eq!(nc.next().await.unwrap()?, "[lobby] grpc left the room");
+        assert!(events.message().await?.is_none());
+        Ok(())
+    }
+}
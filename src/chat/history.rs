@@ -0,0 +1,92 @@
+use dashmap::DashMap;
+use std::{collections::VecDeque, sync::Arc};
+
+use super::Entry;
+
+pub const DEFAULT_HISTORY: usize = 100;
+
+// the most recent messages of every room, oldest first
+#[derive(Debug)]
+pub struct History {
+    rooms: DashMap<String, VecDeque<Arc<Entry>>>,
+    capacity: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY)
+    }
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            rooms: DashMap::new(),
+            capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // keep a room message, evicting the oldest once the room is at capacity
+    pub fn record(&self, entry: Arc<Entry>) {
+        let Some(room) = entry.message.room() else {
+            return;
+        };
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut messages = self.rooms.entry(room.to_string()).or_default();
+        if messages.len() == self.capacity {
+            messages.pop_front();
+        }
+        messages.push_back(entry);
+    }
+
+    // up to `n` latest messages of `room`, oldest first
+    pub fn recent(&self, room: &str, n: usize) -> Vec<Arc<Entry>> {
+        match self.rooms.get(room) {
+            Some(messages) => {
+                let skip = messages.len().saturating_sub(n);
+                messages.iter().skip(skip).cloned().collect()
+            }
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::Message;
+
+    fn entry(message: Message) -> Arc<Entry> {
+        Arc::new(Entry::new(message))
+    }
+
+    #[test]
+    fn history_should_keep_latest_per_room() {
+        let history = History::new(3);
+        for i in 0..5 {
+            history.record(entry(Message::chat("rust", "alice", i.to_string())));
+        }
+        history.record(entry(Message::joined("lobby", "bob")));
+        history.record(entry(Message::system("not kept")));
+
+        let recent = history
+            .recent("rust", 10)
+            .iter()
+            .map(|entry| entry.message.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            recent,
+            vec!["[rust] alice: 2", "[rust] alice: 3", "[rust] alice: 4"]
+        );
+        assert_eq!(history.recent("rust", 1).len(), 1);
+        assert_eq!(history.recent("lobby", 10).len(), 1);
+        assert!(history.recent("go", 10).is_empty());
+    }
+}
@@ -0,0 +1,74 @@
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::time::Instant;
+
+// idle detection, both timers restart whenever the peer sends a line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Keepalive {
+    // disconnect a peer silent for this long, 0 never does
+    pub idle_secs: u64,
+    // send a PING after this much silence, 0 disables pings
+    pub ping_secs: u64,
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Self {
+            idle_secs: 600,
+            ping_secs: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timeout {
+    Ping,
+    Idle,
+}
+
+impl Keepalive {
+    // the next timer to fire given when the peer was last heard from, `None` if disabled
+    pub fn next(&self, last_seen: Instant, pinged: bool) -> Option<(Instant, Timeout)> {
+        let after = |secs| last_seen + Duration::from_secs(secs);
+        let ping = (self.ping_secs > 0 && !pinged).then(|| (after(self.ping_secs), Timeout::Ping));
+        let idle = (self.idle_secs > 0).then(|| (after(self.idle_secs), Timeout::Idle));
+        match (ping, idle) {
+            (Some(ping), Some(idle)) => Some(if ping.0 < idle.0 { ping } else { idle }),
+            (ping, idle) => ping.or(idle),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keepalive_should_ping_before_idle() {
+        let start = Instant::now();
+        let keepalive = Keepalive {
+            idle_secs: 30,
+            ping_secs: 10,
+        };
+        let secs = |n| start + Duration::from_secs(n);
+
+        assert_eq!(
+            keepalive.next(start, false),
+            Some((secs(10), Timeout::Ping))
+        );
+        assert_eq!(keepalive.next(start, true), Some((secs(30), Timeout::Idle)));
+
+        let no_ping = Keepalive {
+            ping_secs: 0,
+            ..keepalive
+        };
+        assert_eq!(no_ping.next(start, false), Some((secs(30), Timeout::Idle)));
+
+        let disabled = Keepalive {
+            idle_secs: 0,
+            ping_secs: 0,
+        };
+        assert_eq!(disabled.next(start, false), None);
+    }
+}
@@ -0,0 +1,176 @@
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+// token bucket settings, every line a peer sends costs one token
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimit {
+    // lines that can be sent back to back
+    pub burst: u32,
+    // tokens added back per second
+    pub per_second: f64,
+    // how long a peer stays muted after running out of tokens
+    pub mute_secs: u64,
+    // mutes before the peer is disconnected
+    pub strikes: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            burst: 10,
+            per_second: 2.0,
+            mute_secs: 10,
+            strikes: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    // ran out of tokens, muted for the given time
+    Mute(Duration),
+    // still muted, the line is dropped
+    Muted,
+    // too many strikes
+    Kick,
+}
+
+#[derive(Debug)]
+pub struct Limiter {
+    limit: RateLimit,
+    tokens: f64,
+    last: Instant,
+    muted_until: Option<Instant>,
+    strikes: u32,
+}
+
+impl Limiter {
+    pub fn new(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            last: now,
+            muted_until: None,
+            strikes: 0,
+        }
+    }
+
+    pub fn strikes(&self) -> u32 {
+        self.strikes
+    }
+
+    // account for one line sent at `now`
+    pub fn check(&mut self, now: Instant) -> Verdict {
+        if self.strikes > 0 && self.has_been_quiet(now) {
+            self.strikes = 0;
+        }
+
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(self.limit.burst as f64);
+        self.last = now;
+
+        if let Some(until) = self.muted_until {
+            if now < until {
+                return Verdict::Muted;
+            }
+            self.muted_until = None;
+        }
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Verdict::Allow;
+        }
+
+        self.strikes += 1;
+        if self.strikes >= self.limit.strikes {
+            return Verdict::Kick;
+        }
+        let mute = Duration::from_secs(self.limit.mute_secs);
+        self.muted_until = Some(now + mute);
+        Verdict::Mute(mute)
+    }
+
+    // whether the bucket has been full for `mute_secs` per strike since the last line, which
+    // forgives the strikes so far
+    fn has_been_quiet(&self, now: Instant) -> bool {
+        let refill = (self.limit.burst as f64 - self.tokens) / self.limit.per_second;
+        // never refills without a rate
+        let Ok(refill) = Duration::try_from_secs_f64(refill) else {
+            return false;
+        };
+        let quiet = Duration::from_secs(self.limit.mute_secs.saturating_mul(self.strikes.into()));
+        now.saturating_duration_since(self.last) >= refill.saturating_add(quiet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: RateLimit = RateLimit {
+        burst: 2,
+        per_second: 1.0,
+        mute_secs: 5,
+        strikes: 2,
+    };
+
+    #[test]
+    fn limiter_should_allow_burst_then_refill() {
+        let start = Instant::now();
+        let mut limiter = Limiter::new(LIMIT, start);
+        assert_eq!(limiter.check(start), Verdict::Allow);
+        assert_eq!(limiter.check(start), Verdict::Allow);
+
+        let later = start + Duration::from_millis(1500);
+        assert_eq!(limiter.check(later), Verdict::Allow);
+        assert_eq!(limiter.strikes(), 0);
+    }
+
+    #[test]
+    fn limiter_should_mute_then_kick() {
+        let start = Instant::now();
+        let mut limiter = Limiter::new(LIMIT, start);
+        limiter.check(start);
+        limiter.check(start);
+
+        assert_eq!(limiter.check(start), Verdict::Mute(Duration::from_secs(5)));
+        assert_eq!(
+            limiter.check(start + Duration::from_secs(4)),
+            Verdict::Muted
+        );
+
+        // the mute is over and the bucket refilled
+        let after = start + Duration::from_secs(6);
+        assert_eq!(limiter.check(after), Verdict::Allow);
+        assert_eq!(limiter.check(after), Verdict::Allow);
+        assert_eq!(limiter.check(after), Verdict::Kick);
+        assert_eq!(limiter.strikes(), 2);
+    }
+
+    #[test]
+    fn limiter_should_forgive_strikes_after_quiet_period() {
+        let start = Instant::now();
+        let mut limiter = Limiter::new(LIMIT, start);
+        for _ in 0..3 {
+            limiter.check(start);
+        }
+        assert_eq!(limiter.strikes(), 1);
+
+        // refilled after 2s, then full for another 5s
+        let later = start + Duration::from_secs(7);
+        for _ in 0..2 {
+            assert_eq!(limiter.check(later), Verdict::Allow);
+        }
+        assert_eq!(limiter.strikes(), 0);
+        assert_eq!(limiter.check(later), Verdict::Mute(Duration::from_secs(5)));
+
+        // not quiet for long enough, a second strike kicks
+        let soon = later + Duration::from_secs(6);
+        for _ in 0..2 {
+            assert_eq!(limiter.check(soon), Verdict::Allow);
+        }
+        assert_eq!(limiter.check(soon), Verdict::Kick);
+    }
+}
@@ -0,0 +1,562 @@
+use anyhow::{anyhow, Result};
+use dashmap::{mapref::entry, DashMap};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    time,
+};
+use tokio_util::codec::{Framed, LinesCodec};
+use tracing::{info, warn};
+
+use super::{Backoff, Entry, Message, State};
+
+// frames waiting for a link before it counts as too slow and is dropped
+const LINK_QUEUE: usize = 1024;
+
+// a burst lists every user behind a server, so frames get a generous limit
+const MAX_FRAME_LENGTH: usize = 1024 * 1024;
+
+type Link = Framed<TcpStream, LinesCodec>;
+
+// what linked servers send each other, one JSON object per line
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Frame {
+    // first frame both ways
+    Hello {
+        server: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        secret: Option<String>,
+    },
+    // every server reachable through the sender, with who is in which room there
+    Burst {
+        servers: Vec<Server>,
+    },
+    // a room message, `id` counts up per origin so a message is never handled twice
+    Event {
+        origin: String,
+        id: u64,
+        entry: Entry,
+    },
+    // servers that became unreachable through the sender
+    Split {
+        servers: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Server {
+    name: String,
+    // username and room
+    users: Vec<(String, String)>,
+}
+
+// this server's view of the others; links form a tree, a server that is already reachable
+// is refused a second link
+#[derive(Debug)]
+pub struct Federation {
+    name: String,
+    secret: Option<String>,
+    // neighbour server name to the queue of its link
+    links: DashMap<String, mpsc::Sender<Arc<Frame>>>,
+    // every reachable server to the neighbour its frames come through
+    routes: DashMap<String, String>,
+    // remote server to its users and their rooms
+    users: DashMap<String, HashMap<String, String>>,
+    // last event id handled per origin server
+    seen: DashMap<String, u64>,
+    // held while an event is queued, so ids reach every link in order
+    next_id: Mutex<u64>,
+}
+
+impl Default for Federation {
+    fn default() -> Self {
+        Self::new("local")
+    }
+}
+
+impl Federation {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            secret: None,
+            links: DashMap::new(),
+            routes: DashMap::new(),
+            users: DashMap::new(),
+            seen: DashMap::new(),
+            next_id: Mutex::new(0),
+        }
+    }
+
+    // links are refused unless both sides know the same secret
+    pub fn with_secret(mut self, secret: impl Into<String>) -> Self {
+        self.secret = Some(secret.into());
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // names of the servers reachable from here, sorted
+    pub fn servers(&self) -> Vec<String> {
+        let mut servers = self
+            .routes
+            .iter()
+            .map(|route| route.key().clone())
+            .collect::<Vec<_>>();
+        servers.sort();
+        servers
+    }
+
+    // a room message of a local peer, for every linked server
+    pub(crate) fn publish(&self, entry: &Entry) {
+        if self.links.is_empty() {
+            return;
+        }
+        let mut next_id = self.next_id.lock().unwrap();
+        let frame = Frame::Event {
+            origin: self.name.clone(),
+            id: *next_id,
+            entry: entry.clone(),
+        };
+        *next_id += 1;
+        self.forward(Arc::new(frame), None);
+    }
+
+    // remote users in `room` as user@server, sorted
+    pub(crate) fn who(&self, room: &str) -> Vec<String> {
+        let mut names = self
+            .users
+            .iter()
+            .flat_map(|server| {
+                server
+                    .value()
+                    .iter()
+                    .filter(|(_, joined)| joined.as_str() == room)
+                    .map(|(username, _)| format!("{}@{}", username, server.key()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    // rooms with remote members and how many
+    pub(crate) fn rooms(&self) -> HashMap<String, usize> {
+        let mut rooms = HashMap::new();
+        for server in self.users.iter() {
+            for room in server.value().values() {
+                *rooms.entry(room.clone()).or_insert(0) += 1;
+            }
+        }
+        rooms
+    }
+
+    fn forward(&self, frame: Arc<Frame>, except: Option<&str>) {
+        let mut slow = Vec::new();
+        for link in self.links.iter() {
+            if Some(link.key().as_str()) == except {
+                continue;
+            }
+            if link.value().try_send(frame.clone()).is_err() {
+                slow.push(link.key().clone());
+            }
+        }
+        // dropping the queue ends the link, which then splits like any other
+        for server in slow {
+            warn!("Link to {} is too slow, dropping it", server);
+            self.links.remove(&server);
+        }
+    }
+
+    // track who is where from the room messages of `origin`
+    fn apply(&self, origin: &str, msg: &Message) {
+        let mut users = self.users.entry(origin.to_string()).or_default();
+        match msg {
+            Message::UserJoined { room, username } => {
+                users.insert(username.clone(), room.clone());
+            }
+            Message::UserLeft { room, username } if users.get(username) == Some(room) => {
+                users.remove(username);
+            }
+            Message::Renamed { room, from, to } => {
+                users.remove(from);
+                users.insert(to.clone(), room.clone());
+            }
+            _ => {}
+        }
+    }
+}
+
+// accept links from other servers until shutdown
+pub async fn serve_links(addr: &str, state: Arc<State>) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!(
+        "Chat server {} accepting links on {}",
+        state.federation().name,
+        addr
+    );
+    accept_links(listener, state).await
+}
+
+async fn accept_links(listener: TcpListener, state: Arc<State>) -> Result<()> {
+    loop {
+        let (stream, addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = state.closing() => return Ok(()),
+        };
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = run_link(&state, stream, addr).await {
+                warn!("Failed to link with {}: {:#}", addr, e);
+            }
+        });
+    }
+}
+
+// keep a link to the server at `addr`, dialing again with backoff whenever it drops
+pub async fn link_to(addr: String, state: Arc<State>) -> Result<()> {
+    let mut delays = Backoff::default().delays();
+    loop {
+        let linked = async {
+            let stream = TcpStream::connect(&addr).await?;
+            let peer = stream.peer_addr()?;
+            run_link(&state, stream, peer).await
+        };
+        match linked.await {
+            // the link was up, so the next failure starts over with a short delay
+            Ok(()) => delays = Backoff::default().delays(),
+            Err(e) => warn!("Failed to link with {}: {:#}", addr, e),
+        }
+
+        let delay = delays.next().unwrap_or_default();
+        tokio::select! {
+            _ = time::sleep(delay) => {}
+            _ = state.closing() => return Ok(()),
+        }
+    }
+}
+
+// the handshake is the only part that fails, a link that was up and dropped is a netsplit
+async fn run_link(state: &State, stream: TcpStream, addr: SocketAddr) -> Result<()> {
+    let fed = state.federation();
+    let mut link = Framed::new(stream, LinesCodec::new_with_max_length(MAX_FRAME_LENGTH));
+
+    let hello = Frame::Hello {
+        server: fed.name.clone(),
+        secret: fed.secret.clone(),
+    };
+    send(&mut link, &hello).await?;
+    let server = match recv(&mut link).await? {
+        Some(Frame::Hello { server, secret }) if secret == fed.secret => server,
+        Some(Frame::Hello { .. }) => return Err(anyhow!("wrong link secret")),
+        _ => return Err(anyhow!("expected a hello")),
+    };
+    if server == fed.name {
+        return Err(anyhow!("server {} has our name", server));
+    }
+    match fed.routes.entry(server.clone()) {
+        entry::Entry::Occupied(_) => {
+            return Err(anyhow!("server {} is already linked", server));
+        }
+        entry::Entry::Vacant(route) => {
+            route.insert(server.clone());
+        }
+    }
+    let (tx, mut rx) = mpsc::channel(LINK_QUEUE);
+    fed.links.insert(server.clone(), tx);
+    info!("Linked with server {} at {}", server, addr);
+
+    let burst = Frame::Burst {
+        servers: burst(state, &server),
+    };
+    let result = match send(&mut link, &burst).await {
+        Ok(()) => loop {
+            tokio::select! {
+                frame = rx.recv() => match frame {
+                    Some(frame) => {
+                        if let Err(e) = send(&mut link, &frame).await {
+                            break Err(e);
+                        }
+                    }
+                    None => break Err(anyhow!("too slow")),
+                },
+                frame = recv(&mut link) => match frame {
+                    Ok(Some(frame)) => handle(state, &server, frame),
+                    Ok(None) => break Ok(()),
+                    Err(e) => break Err(e),
+                },
+                _ = state.closing() => break Ok(()),
+            }
+        },
+        Err(e) => Err(e),
+    };
+
+    fed.links.remove(&server);
+    split(state, &server);
+    match result {
+        Ok(()) => warn!("Netsplit, link with server {} closed", server),
+        Err(e) => warn!("Netsplit, link with server {} failed: {:#}", server, e),
+    }
+    Ok(())
+}
+
+// this server and everything behind it, for a neighbour that just linked
+fn burst(state: &State, to: &str) -> Vec<Server> {
+    let fed = state.federation();
+    let mut servers = vec![Server {
+        name: fed.name.clone(),
+        users: state.members(),
+    }];
+    for route in fed.routes.iter().filter(|route| route.value() != to) {
+        let users = fed
+            .users
+            .get(route.key())
+            .map(|users| users.iter().map(|(u, r)| (u.clone(), r.clone())).collect())
+            .unwrap_or_default();
+        servers.push(Server {
+            name: route.key().clone(),
+            users,
+        });
+    }
+    servers
+}
+
+// a frame from the neighbour `via`
+fn handle(state: &State, via: &str, frame: Frame) {
+    let fed = state.federation();
+    match frame {
+        Frame::Hello { .. } => warn!("Unexpected hello from server {}", via),
+        Frame::Burst { servers } => {
+            let mut joined = Vec::new();
+            for server in servers {
+                // the neighbour itself was routed by the handshake, any other server already
+                // reachable another way would close a loop
+                let known = server.name != via && fed.routes.contains_key(&server.name);
+                if server.name == fed.name || known {
+                    warn!(
+                        "Ignoring server {} behind {}, already linked",
+                        server.name, via
+                    );
+                    continue;
+                }
+                fed.routes.insert(server.name.clone(), via.to_string());
+                for (username, room) in &server.users {
+                    let msg = Message::joined(room, username);
+                    fed.apply(&server.name, &msg);
+                    state.deliver(Arc::new(Entry::new(qualify(msg, &server.name))));
+                }
+                joined.push(server);
+            }
+            if !joined.is_empty() {
+                fed.forward(Arc::new(Frame::Burst { servers: joined }), Some(via));
+            }
+        }
+        Frame::Event { origin, id, entry } => {
+            // in a tree, events of a server only ever come from the link towards it
+            if fed.routes.get(&origin).is_none_or(|hop| hop.value() != via) {
+                warn!("Ignoring event of server {} from {}", origin, via);
+                return;
+            }
+            match fed.seen.entry(origin.clone()) {
+                entry::Entry::Occupied(last) if *last.get() >= id => return,
+                entry::Entry::Occupied(mut last) => {
+                    last.insert(id);
+                }
+                entry::Entry::Vacant(last) => {
+                    last.insert(id);
+                }
+            }
+            fed.apply(&origin, &entry.message);
+            state.deliver(Arc::new(Entry {
+                at: entry.at,
+                message: qualify(entry.message.clone(), &origin),
+            }));
+            let frame = Frame::Event { origin, id, entry };
+            fed.forward(Arc::new(frame), Some(via));
+        }
+        Frame::Split { servers } => {
+            let lost = servers
+                .into_iter()
+                .filter(|server| fed.routes.remove_if(server, |_, hop| hop == via).is_some())
+                .collect::<Vec<_>>();
+            drop_servers(state, lost);
+        }
+    }
+}
+
+// the link to `via` is gone, along with every server behind it
+fn split(state: &State, via: &str) {
+    let fed = state.federation();
+    let lost = fed
+        .routes
+        .iter()
+        .filter(|route| route.value() == via)
+        .map(|route| route.key().clone())
+        .collect::<Vec<_>>();
+    for server in &lost {
+        fed.routes.remove(server);
+    }
+    drop_servers(state, lost);
+}
+
+// their users leave every room here, and the rest of the tree is told
+fn drop_servers(state: &State, servers: Vec<String>) {
+    let fed = state.federation();
+    if servers.is_empty() {
+        return;
+    }
+    for server in &servers {
+        fed.seen.remove(server);
+        let Some((_, users)) = fed.users.remove(server) else {
+            continue;
+        };
+        for (username, room) in users {
+            let msg = qualify(Message::left(room, username), server);
+            state.deliver(Arc::new(Entry::new(msg)));
+        }
+    }
+    fed.forward(Arc::new(Frame::Split { servers }), None);
+}
+
+// remote users show up as user@server, so they cannot pass for local ones
+fn qualify(msg: Message, server: &str) -> Message {
+    let at = |username: String| format!("{}@{}", username, server);
+    match msg {
+        Message::UserJoined { room, username } => Message::joined(room, at(username)),
+        Message::UserLeft { room, username } => Message::left(room, at(username)),
+        Message::Chat {
+            room,
+            sender,
+            content,
+        } => Message::chat(room, at(sender), content),
+        Message::Renamed { room, from, to } => Message::renamed(room, at(from), at(to)),
+        msg => msg,
+    }
+}
+
+async fn send(link: &mut Link, frame: &Frame) -> Result<()> {
+    link.send(serde_json::to_string(frame)?).await?;
+    Ok(())
+}
+
+// `None` once the other side closed the link
+async fn recv(link: &mut Link) -> Result<Option<Frame>> {
+    match link.next().await {
+        Some(line) => Ok(Some(serde_json::from_str(&line?)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::tests::{connect, wait_for};
+
+    async fn listen(state: &Arc<State>) -> Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?.to_string();
+        tokio::spawn(accept_links(listener, state.clone()));
+        Ok(addr)
+    }
+
+    fn server(name: &str) -> Arc<State> {
+        Arc::new(State::default().with_federation(Federation::new(name)))
+    }
+
+    async fn linked(state: &State, count: usize) {
+        while state.federation().servers().len() < count {
+            time::sleep(time::Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn linked_servers_should_share_rooms() -> Result<()> {
+        let (a, b) = (server("a"), server("b"));
+        let (alice_tx, mut alice_rx) = connect(&a, 1001);
+        alice_tx.unbounded_send("alice".to_string())?;
+        wait_for(&mut alice_rx, "* You joined lobby").await;
+
+        let addr = listen(&a).await?;
+        let link = tokio::spawn(link_to(addr, b.clone()));
+        linked(&b, 1).await;
+
+        let (bob_tx, mut bob_rx) = connect(&b, 1001);
+        bob_tx.unbounded_send("bob".to_string())?;
+        wait_for(&mut alice_rx, "[lobby] bob@b joined the room").await;
+        bob_tx.unbounded_send("hi from b".to_string())?;
+        wait_for(&mut alice_rx, "[lobby] bob@b: hi from b").await;
+        alice_tx.unbounded_send("hi from a".to_string())?;
+        wait_for(&mut bob_rx, "[lobby] alice@a: hi from a").await;
+
+        alice_tx.unbounded_send("/who".to_string())?;
+        wait_for(&mut alice_rx, "* In lobby: alice, bob@b").await;
+        // alice was there before the link, b learned of her from the burst
+        bob_tx.unbounded_send("/who".to_string())?;
+        wait_for(&mut bob_rx, "* In lobby: bob, alice@a").await;
+
+        // netsplit, bob is gone from a until the link is back
+        link.abort();
+        wait_for(&mut alice_rx, "[lobby] bob@b left the room").await;
+        alice_tx.unbounded_send("/who".to_string())?;
+        wait_for(&mut alice_rx, "* In lobby: alice").await;
+        assert!(a.federation().servers().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn links_should_not_form_loops() -> Result<()> {
+        let (a, b, c) = (server("a"), server("b"), server("c"));
+        let a_addr = listen(&a).await?;
+        let b_addr = listen(&b).await?;
+        tokio::spawn(link_to(a_addr.clone(), b.clone()));
+        tokio::spawn(link_to(a_addr, c.clone()));
+        linked(&c, 2).await;
+        linked(&b, 2).await;
+        assert_eq!(a.federation().servers(), vec!["b", "c"]);
+        assert_eq!(c.federation().servers(), vec!["a", "b"]);
+
+        // c already reaches b through a, so a direct link is refused
+        let stream = TcpStream::connect(&b_addr).await?;
+        let peer = stream.peer_addr()?;
+        assert!(run_link(&c, stream, peer).await.is_err());
+
+        let (carol_tx, mut carol_rx) = connect(&c, 1001);
+        carol_tx.unbounded_send("carol".to_string())?;
+        wait_for(&mut carol_rx, "* You joined lobby").await;
+        let (bob_tx, mut bob_rx) = connect(&b, 1001);
+        bob_tx.unbounded_send("bob".to_string())?;
+        wait_for(&mut carol_rx, "[lobby] bob@b joined the room").await;
+        wait_for(&mut bob_rx, "* You joined lobby").await;
+
+        bob_tx.unbounded_send("once".to_string())?;
+        wait_for(&mut carol_rx, "[lobby] bob@b: once").await;
+
+        // the same event coming round again is dropped
+        let id = *c.federation().seen.get("b").unwrap();
+        let frame = Frame::Event {
+            origin: "b".to_string(),
+            id,
+            entry: Entry::new(Message::chat("lobby", "bob", "once")),
+        };
+        handle(&c, "a", frame);
+        // and so is one claiming to come from b over a link b is not behind
+        let frame = Frame::Event {
+            origin: "b".to_string(),
+            id: id + 1,
+            entry: Entry::new(Message::chat("lobby", "bob", "forged")),
+        };
+        handle(&c, "d", frame);
+        bob_tx.unbounded_send("twice?".to_string())?;
+        assert_eq!(carol_rx.next().await.unwrap(), "[lobby] bob@b: twice?");
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+    thread,
+};
+use tracing::warn;
+
+use super::{history::History, Entry};
+
+const PREFIX: &str = "chat-";
+const SUFFIX: &str = ".jsonl";
+
+// days of logs replayed at startup, so startup does not slow down as logs pile up; rooms quiet
+// for longer start with an empty history
+const RESTORE_DAYS: usize = 7;
+
+// append-only JSON lines log, one file per day named chat-YYYY-MM-DD.jsonl;
+// writes happen on a background thread so appending never blocks the caller
+#[derive(Debug)]
+pub struct ChatLog {
+    dir: PathBuf,
+    tx: mpsc::Sender<Entry>,
+}
+
+impl ChatLog {
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create chat log dir {}", dir.display()))?;
+
+        let (tx, rx) = mpsc::channel::<Entry>();
+        let mut writer = Writer::new(dir.clone());
+        thread::Builder::new()
+            .name("chat-log".to_string())
+            .spawn(move || {
+                for entry in rx {
+                    if let Err(e) = writer.write(&entry) {
+                        warn!("Failed to write chat log: {:#}", e);
+                    }
+                }
+            })?;
+
+        Ok(Self { dir, tx })
+    }
+
+    pub fn append(&self, entry: &Entry) {
+        // only fails when the writer thread is gone, which it already logged
+        let _ = self.tx.send(entry.clone());
+    }
+
+    // replay the latest `RESTORE_DAYS` log files, oldest day first, into `history`; returns the
+    // entry count
+    pub fn restore(&self, history: &History) -> Result<usize> {
+        if history.capacity() == 0 {
+            return Ok(0);
+        }
+
+        let files = log_files(&self.dir)?;
+        let mut count = 0;
+        for path in &files[files.len().saturating_sub(RESTORE_DAYS)..] {
+            let file = File::open(path)
+                .with_context(|| format!("Failed to open chat log {}", path.display()))?;
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                match serde_json::from_str::<Entry>(&line) {
+                    Ok(entry) => {
+                        history.record(Arc::new(entry));
+                        count += 1;
+                    }
+                    // a torn last line after a crash should not stop the server
+                    Err(e) => warn!("Skipping bad line in {}: {}", path.display(), e),
+                }
+            }
+        }
+        Ok(count)
+    }
+}
+
+struct Writer {
+    dir: PathBuf,
+    current: Option<(NaiveDate, File)>,
+}
+
+impl Writer {
+    fn new(dir: PathBuf) -> Self {
+        Self { dir, current: None }
+    }
+
+    fn write(&mut self, entry: &Entry) -> Result<()> {
+        let date = entry.at.date_naive();
+        let file = match &mut self.current {
+            Some((day, file)) if *day == date => file,
+            _ => {
+                let path = self.dir.join(file_name(date));
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .read(true)
+                    .open(&path)
+                    .with_context(|| format!("Failed to open chat log {}", path.display()))?;
+                terminate_last_line(&mut file)?;
+                &mut self.current.insert((date, file)).1
+            }
+        };
+
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        file.write_all(&line)?;
+        Ok(())
+    }
+}
+
+// a crash can leave a torn last line, start the next entry on a line of its own
+fn terminate_last_line(file: &mut File) -> Result<()> {
+    let len = file.metadata()?.len();
+    if len == 0 {
+        return Ok(());
+    }
+
+    let mut last = [0u8];
+    file.seek(SeekFrom::Start(len - 1))?;
+    file.read_exact(&mut last)?;
+    if last[0] != b'\n' {
+        file.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+fn file_name(date: NaiveDate) -> String {
+    format!("{}{}{}", PREFIX, date.format("%Y-%m-%d"), SUFFIX)
+}
+
+// log files in `dir` sorted by day
+fn log_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_log = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(PREFIX)?.strip_suffix(SUFFIX))
+            .is_some_and(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").is_ok());
+        if is_log {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::Message;
+    use chrono::{TimeZone, Utc};
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("chat-log-{}", nanoid::nanoid!()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn writer_should_rotate_daily() -> Result<()> {
+        let dir = temp_dir();
+        let mut writer = Writer::new(dir.clone());
+        let day1 = Utc.with_ymd_and_hms(2024, 10, 1, 23, 59, 0).unwrap();
+        let day2 = Utc.with_ymd_and_hms(2024, 10, 2, 0, 1, 0).unwrap();
+
+        for (at, content) in [(day1, "late"), (day2, "early"), (day2, "again")] {
+            let message = Message::chat("rust", "alice", content);
+            writer.write(&Entry { at, message })?;
+        }
+        fs::write(dir.join("notes.txt"), "not a log")?;
+
+        let files = log_files(&dir)?;
+        assert_eq!(
+            files,
+            vec![
+                dir.join("chat-2024-10-01.jsonl"),
+                dir.join("chat-2024-10-02.jsonl")
+            ]
+        );
+        assert_eq!(fs::read_to_string(&files[1])?.lines().count(), 2);
+
+        // a torn line is skipped on restore, and later entries start on a fresh line
+        let mut file = OpenOptions::new().append(true).open(&files[1])?;
+        file.write_all(b"{\"at\":")?;
+        let mut writer = Writer::new(dir.clone());
+        let message = Message::chat("rust", "alice", "after crash");
+        writer.write(&Entry { at: day2, message })?;
+
+        let log = ChatLog::open(&dir)?;
+        let history = History::new(2);
+        assert_eq!(log.restore(&history)?, 4);
+        let recent = history
+            .recent("rust", 10)
+            .iter()
+            .map(|entry| entry.message.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            recent,
+            vec!["[rust] alice: again", "[rust] alice: after crash"]
+        );
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn restore_should_only_read_recent_days() -> Result<()> {
+        let dir = temp_dir();
+        let mut writer = Writer::new(dir.clone());
+        for day in 1..=RESTORE_DAYS as u32 + 2 {
+            let at = Utc.with_ymd_and_hms(2024, 10, day, 12, 0, 0).unwrap();
+            let message = Message::chat("rust", "alice", format!("day {}", day));
+            writer.write(&Entry { at, message })?;
+        }
+
+        let log = ChatLog::open(&dir)?;
+        let history = History::new(100);
+        assert_eq!(log.restore(&history)?, RESTORE_DAYS);
+        let oldest = history.recent("rust", 100)[0].message.to_string();
+        assert_eq!(oldest, "[rust] alice: day 3");
+        assert_eq!(log.restore(&History::new(0))?, 0);
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
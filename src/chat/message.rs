@@ -0,0 +1,214 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    #[serde(rename = "joined")]
+    UserJoined {
+        room: String,
+        username: String,
+    },
+    #[serde(rename = "left")]
+    UserLeft {
+        room: String,
+        username: String,
+    },
+    Chat {
+        room: String,
+        sender: String,
+        content: String,
+    },
+    Renamed {
+        room: String,
+        from: String,
+        to: String,
+    },
+    // direct message, only delivered to the recipient
+    Private {
+        sender: String,
+        content: String,
+    },
+    // server reply to a single peer
+    System {
+        text: String,
+    },
+    // a request of the peer failed
+    Error {
+        text: String,
+    },
+    // keepalive probe, answered with a pong
+    Ping,
+    // token to resume the session with after losing the connection
+    Resume {
+        token: String,
+    },
+}
+
+// a message stamped with the time the server handled it, as logged and sent to JSON clients
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub message: Message,
+}
+
+impl Message {
+    pub fn joined(room: impl Into<String>, username: impl Into<String>) -> Self {
+        Self::UserJoined {
+            room: room.into(),
+            username: username.into(),
+        }
+    }
+
+    pub fn left(room: impl Into<String>, username: impl Into<String>) -> Self {
+        Self::UserLeft {
+            room: room.into(),
+            username: username.into(),
+        }
+    }
+
+    pub fn chat(
+        room: impl Into<String>,
+        sender: impl Into<String>,
+        content: impl Into<String>,
+    ) -> Self {
+        Self::Chat {
+            room: room.into(),
+            sender: sender.into(),
+            content: content.into(),
+        }
+    }
+
+    pub fn renamed(
+        room: impl Into<String>,
+        from: impl Into<String>,
+        to: impl Into<String>,
+    ) -> Self {
+        Self::Renamed {
+            room: room.into(),
+            from: from.into(),
+            to: to.into(),
+        }
+    }
+
+    pub fn private(sender: impl Into<String>, content: impl Into<String>) -> Self {
+        Self::Private {
+            sender: sender.into(),
+            content: content.into(),
+        }
+    }
+
+    pub fn system(text: impl Into<String>) -> Self {
+        Self::System { text: text.into() }
+    }
+
+    pub fn error(text: impl Into<String>) -> Self {
+        Self::Error { text: text.into() }
+    }
+
+    pub fn resume(token: impl Into<String>) -> Self {
+        Self::Resume {
+            token: token.into(),
+        }
+    }
+
+    // room the message belongs to, `None` for messages to a single peer
+    pub fn room(&self) -> Option<&str> {
+        match self {
+            Message::UserJoined { room, .. }
+            | Message::UserLeft { room, .. }
+            | Message::Chat { room, .. }
+            | Message::Renamed { room, .. } => Some(room),
+            Message::Private { .. }
+            | Message::System { .. }
+            | Message::Error { .. }
+            | Message::Ping
+            | Message::Resume { .. } => None,
+        }
+    }
+}
+
+impl Entry {
+    pub fn new(message: Message) -> Self {
+        Self {
+            at: Utc::now(),
+            message,
+        }
+    }
+}
+
+impl Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Message::UserJoined { room, username } => {
+                write!(f, "[{}] {} joined the room", room, username)
+            }
+            Message::UserLeft { room, username } => {
+                write!(f, "[{}] {} left the room", room, username)
+            }
+            Message::Chat {
+                room,
+                sender,
+                content,
+            } => write!(f, "[{}] {}: {}", room, sender, content),
+            Message::Renamed { room, from, to } => {
+                write!(f, "[{}] {} is now known as {}", room, from, to)
+            }
+            Message::Private { sender, content } => write!(f, "[pm] {}: {}", sender, content),
+            // plain text clients see errors as any other notice
+            Message::System { text } | Message::Error { text } => write!(f, "* {}", text),
+            Message::Ping => write!(f, "PING"),
+            Message::Resume { token } => write!(f, "* Resume token: {}", token),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_display_should_work() {
+        assert_eq!(
+            Message::joined("lobby", "alice").to_string(),
+            "[lobby] alice joined the room"
+        );
+        assert_eq!(
+            Message::left("rust", "alice").to_string(),
+            "[rust] alice left the room"
+        );
+        assert_eq!(
+            Message::chat("rust", "alice", "hello").to_string(),
+            "[rust] alice: hello"
+        );
+        assert_eq!(
+            Message::renamed("rust", "alice", "alicia").to_string(),
+            "[rust] alice is now known as alicia"
+        );
+        assert_eq!(
+            Message::private("alice", "psst").to_string(),
+            "[pm] alice: psst"
+        );
+        assert_eq!(
+            Message::system("Rooms: lobby").to_string(),
+            "* Rooms: lobby"
+        );
+    }
+
+    #[test]
+    fn message_should_serialize_with_type_tag() -> anyhow::Result<()> {
+        let msg = Message::chat("rust", "alice", "hello");
+        let json = serde_json::to_string(&msg)?;
+        assert_eq!(
+            json,
+            r#"{"type":"chat","room":"rust","sender":"alice","content":"hello"}"#
+        );
+        assert_eq!(serde_json::from_str::<Message>(&json)?, msg);
+
+        let json = serde_json::to_string(&Message::joined("lobby", "bob"))?;
+        assert_eq!(json, r#"{"type":"joined","room":"lobby","username":"bob"}"#);
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+use opentelemetry::{
+    global,
+    metrics::{Counter, Histogram, Meter, UpDownCounter},
+};
+use std::time::Duration;
+
+// instruments of the chat server, a no-op unless the meter comes from a configured provider
+#[derive(Debug, Clone)]
+pub struct ChatMetrics {
+    peers: UpDownCounter<i64>,
+    rooms: UpDownCounter<i64>,
+    received: Counter<u64>,
+    sent: Counter<u64>,
+    dropped: Counter<u64>,
+    queue_depth: Histogram<u64>,
+    broadcast: Histogram<f64>,
+}
+
+impl Default for ChatMetrics {
+    fn default() -> Self {
+        Self::new(&global::meter("chat"))
+    }
+}
+
+impl ChatMetrics {
+    pub fn new(meter: &Meter) -> Self {
+        Self {
+            peers: meter
+                .i64_up_down_counter("chat.peers")
+                .with_description("Sessions, including those waiting for a resume")
+                .build(),
+            rooms: meter
+                .i64_up_down_counter("chat.rooms")
+                .with_description("Rooms with at least one member")
+                .build(),
+            received: meter
+                .u64_counter("chat.messages.in")
+                .with_description("Lines received from logged in peers")
+                .build(),
+            sent: meter
+                .u64_counter("chat.messages.out")
+                .with_description("Messages written to peers")
+                .build(),
+            dropped: meter
+                .u64_counter("chat.messages.dropped")
+                .with_description("Messages lost to full peer queues")
+                .build(),
+            queue_depth: meter
+                .u64_histogram("chat.queue.depth")
+                .with_description("Messages waiting in a peer's queue once one is added")
+                .with_boundaries(vec![0.0, 1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0])
+                .build(),
+            broadcast: meter
+                .f64_histogram("chat.broadcast.duration")
+                .with_description("Time taken to queue a message for every room member")
+                .with_unit("s")
+                .with_boundaries(vec![
+                    0.00001, 0.00005, 0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05,
+                ])
+                .build(),
+        }
+    }
+
+    pub(crate) fn peer_added(&self) {
+        self.peers.add(1, &[]);
+    }
+
+    pub(crate) fn peer_removed(&self) {
+        self.peers.add(-1, &[]);
+    }
+
+    pub(crate) fn room_opened(&self) {
+        self.rooms.add(1, &[]);
+    }
+
+    pub(crate) fn room_closed(&self) {
+        self.rooms.add(-1, &[]);
+    }
+
+    pub(crate) fn received(&self) {
+        self.received.add(1, &[]);
+    }
+
+    pub(crate) fn sent(&self) {
+        self.sent.add(1, &[]);
+    }
+
+    pub(crate) fn dropped(&self, count: u64) {
+        self.dropped.add(count, &[]);
+    }
+
+    pub(crate) fn queued(&self, depth: usize) {
+        self.queue_depth.record(depth as u64, &[]);
+    }
+
+    pub(crate) fn broadcast(&self, elapsed: Duration) {
+        self.broadcast.record(elapsed.as_secs_f64(), &[]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chat::{Message, State},
+        telemetry::Prometheus,
+    };
+    use anyhow::Result;
+    use opentelemetry::metrics::MeterProvider;
+    use opentelemetry_sdk::metrics::SdkMeterProvider;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn state_should_record_metrics() -> Result<()> {
+        let prometheus = Prometheus::new();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(prometheus.reader()?)
+            .build();
+        let state = State::default().with_metrics(ChatMetrics::new(&provider.meter("chat")));
+
+        let peers = [1001, 1002, 1003].map(|port| SocketAddr::from(([127, 0, 0, 1], port)));
+        for (addr, name) in peers.iter().zip(["alice", "bob", "carol"]) {
+            state.register(*addr, name.to_string());
+        }
+        state.join(peers[0], "lobby");
+        state.join(peers[1], "lobby");
+        state.join(peers[2], "rust");
+        state.broadcast("lobby", peers[0], Message::chat("lobby", "alice", "hi"));
+        state.remove(&peers[2]);
+
+        let text = prometheus.render()?;
+        for line in [
+            "# TYPE chat_peers gauge",
+            "chat_peers 2",
+            "chat_rooms 1",
+            "# TYPE chat_queue_depth histogram",
+            "chat_queue_depth_bucket{le=\"0\"} 0",
+            "chat_queue_depth_bucket{le=\"1\"} 1",
+            "chat_queue_depth_count 1",
+            "chat_broadcast_duration_seconds_count 1",
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "{:?} not in\n{}",
+                line,
+                text
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1166 @@
+mod account;
+mod ban;
+mod bots;
+mod client;
+mod command;
+mod grpc;
+mod history;
+mod keepalive;
+mod limit;
+mod link;
+mod log;
+mod message;
+mod metrics;
+mod outbox;
+mod plugin;
+mod protocol;
+mod state;
+mod store;
+mod tls;
+mod ws;
+
+pub use account::{Account, Accounts};
+pub use ban::{Ban, BanTarget, Bans};
+pub use bots::{bot, Dice, Reminder, Title};
+pub use client::{Backoff, Client};
+pub use command::Command;
+pub use grpc::{serve_grpc, ChatGrpc};
+pub use history::{History, DEFAULT_HISTORY};
+pub use keepalive::Keepalive;
+pub use limit::{Limiter, RateLimit, Verdict};
+pub use link::{link_to, serve_links, Federation};
+pub use log::ChatLog;
+pub use message::{Entry, Message};
+pub use metrics::ChatMetrics;
+pub use outbox::Overflow;
+pub use plugin::{Action, Context, Plugin, Plugins, DEFAULT_PLUGIN_TIMEOUT};
+pub use protocol::{Protocol, Request};
+pub use state::{LineSink, LineStream, Peer, State, Stats, DEFAULT_RESUME_GRACE, DEFAULT_ROOM};
+pub use tls::{acceptor, serve_tls, DEFAULT_TLS_ADDR};
+pub use ws::{router, serve_ws};
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use futures::{future, SinkExt, StreamExt, TryStreamExt};
+use keepalive::Timeout;
+use state::Reservation;
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+    time,
+};
+use tokio_util::codec::{Framed, LinesCodec, LinesCodecError};
+use tracing::{info, warn};
+
+pub const DEFAULT_ADDR: &str = "0.0.0.0:3000";
+
+// attempts at picking a valid, free username before the client is dropped
+const MAX_USERNAME_ATTEMPTS: usize = 3;
+
+// longest line a client may send, in bytes
+pub const MAX_LINE_LENGTH: usize = 1024;
+
+// how long a shutdown waits for peers to receive what is queued for them
+pub const SHUTDOWN_DEADLINE: Duration = Duration::from_secs(5);
+
+pub async fn serve(addr: &str) -> Result<()> {
+    serve_with(addr, Arc::new(State::default())).await
+}
+
+pub async fn serve_with(addr: &str, state: Arc<State>) -> Result<()> {
+    ChatServer::bind_with(addr, state).await?.run().await
+}
+
+// a bound plain TCP listener, so the port is known before serving, e.g. after binding port 0
+#[derive(Debug)]
+pub struct ChatServer {
+    listener: TcpListener,
+    state: Arc<State>,
+}
+
+impl ChatServer {
+    pub async fn bind(addr: &str) -> Result<Self> {
+        Self::bind_with(addr, Arc::new(State::default())).await
+    }
+
+    pub async fn bind_with(addr: &str, state: Arc<State>) -> Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        info!("Chat server listening on {}", listener.local_addr()?);
+        Ok(Self { listener, state })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    pub fn port(&self) -> Result<u16> {
+        Ok(self.local_addr()?.port())
+    }
+
+    // shared with the server, e.g. to shut it down
+    pub fn state(&self) -> &Arc<State> {
+        &self.state
+    }
+
+    // accept clients until the state starts shutting down
+    pub async fn run(self) -> Result<()> {
+        accept(self.listener, self.state, |stream| {
+            future::ready(Ok(stream))
+        })
+        .await
+    }
+}
+
+// accept clients until shutdown, `upgrade` turns each connection into the stream the session reads,
+// e.g. after a TLS handshake
+pub(crate) async fn accept<F, Fut, S>(
+    listener: TcpListener,
+    state: Arc<State>,
+    upgrade: F,
+) -> Result<()>
+where
+    F: Fn(TcpStream) -> Fut,
+    Fut: Future<Output = io::Result<S>> + Send + 'static,
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let local = listener.local_addr()?;
+    loop {
+        let (stream, addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = state.closing() => {
+                info!("Chat server stopped accepting on {}", local);
+                return Ok(());
+            }
+        };
+        // dropping the stream closes the connection before any byte is read
+        if state.bans().is_banned_ip(addr.ip()) {
+            info!("Rejected banned client {}", addr);
+            continue;
+        }
+        info!("Client {} connected", addr);
+
+        // handle client in a new task
+        let state_cloned = Arc::clone(&state);
+        let upgrading = upgrade(stream);
+        tokio::spawn(async move {
+            let stream = match upgrading.await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Failed to set up connection with {}: {}", addr, e);
+                    return;
+                }
+            };
+            if let Err(e) = handle_client(state_cloned, addr, stream).await {
+                warn!("Failed to handle client: {}, {}", addr, e);
+            }
+        });
+    }
+}
+
+// resolves on Ctrl-C, or SIGTERM on unix
+pub async fn shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut term = signal(SignalKind::terminate())?;
+        tokio::select! {
+            ctrl_c = tokio::signal::ctrl_c() => ctrl_c?,
+            _ = term.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+
+    info!("Received shutdown signal");
+    Ok(())
+}
+
+// any byte stream works, plain TCP or TLS alike
+pub async fn handle_client<S>(state: Arc<State>, addr: SocketAddr, stream: S) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    // split stream into lines codec
+    let codec = LinesCodec::new_with_max_length(MAX_LINE_LENGTH);
+    let (sink, stream) = Framed::new(stream, codec).split();
+    let sink = Box::pin(sink.sink_map_err(anyhow::Error::from));
+    let stream = stream
+        .map_err(|e| match e {
+            LinesCodecError::MaxLineLengthExceeded => line_too_long(),
+            e => e.into(),
+        })
+        .boxed();
+    handle_lines(state, addr, sink, stream).await
+}
+
+// the chat session itself, shared by every transport
+pub(crate) async fn handle_lines(
+    state: Arc<State>,
+    addr: SocketAddr,
+    mut sink: LineSink,
+    stream: LineStream,
+) -> Result<()> {
+    let Some(reservation) = state.reserve(addr) else {
+        let msg = Entry::new(Message::error("Address in use, reconnect and try again"));
+        let _ = sink.send(Protocol::default().encode(&msg)).await;
+        anyhow::bail!("Another session is using {}", addr);
+    };
+    handle_session(state, reservation, Protocol::default(), sink, stream).await
+}
+
+// like `handle_lines`, for transports whose clients never negotiate a protocol
+pub(crate) async fn handle_session(
+    state: Arc<State>,
+    reservation: Reservation,
+    mut protocol: Protocol,
+    mut sink: LineSink,
+    mut stream: LineStream,
+) -> Result<()> {
+    let addr = reservation.addr();
+    let mut attempts = 0;
+    // a registered username waiting for its password
+    let mut pending: Option<String> = None;
+    let start = loop {
+        if attempts == MAX_USERNAME_ATTEMPTS {
+            let msg = Entry::new(Message::error("Too many attempts, bye"));
+            sink.send(protocol.encode(&msg)).await?;
+            return Ok(());
+        }
+        attempts += 1;
+
+        let prompt = match &pending {
+            Some(username) => format!("Password for {}: ", username),
+            None => "Enter your username: ".to_string(),
+        };
+        sink.send(protocol.prompt(&prompt)).await?;
+        let line = tokio::select! {
+            line = stream.next() => line,
+            _ = state.closing() => {
+                let msg = Entry::new(Message::system("Server shutting down"));
+                sink.send(protocol.encode(&msg)).await?;
+                return Ok(());
+            }
+        };
+        let line = match line {
+            Some(Ok(line)) => line,
+            Some(Err(e)) => {
+                // best effort, the connection may already be gone
+                let msg = Entry::new(Message::error(format!("{:#}", e)));
+                let _ = sink.send(protocol.encode(&msg)).await;
+                return Err(e);
+            }
+            None => return Ok(()),
+        };
+
+        let reply = match (pending.take(), Protocol::negotiate(&line)) {
+            (Some(username), _) => match password(protocol, &line) {
+                Ok(password) => match authenticate(&state, addr, &username, password).await {
+                    Ok(()) => break Start::Login(username, true),
+                    Err(e) => Message::error(e.to_string()),
+                },
+                Err(e) => Message::error(e.to_string()),
+            },
+            // switching protocol does not use up an attempt
+            (None, Some(Ok(switched))) => {
+                protocol = switched;
+                attempts -= 1;
+                Message::system(format!("Protocol set to {}", protocol.name()))
+            }
+            (None, Some(Err(e))) => Message::error(e.to_string()),
+            (None, None) => match resume_token(protocol, &line) {
+                Some(token) if state.can_resume(&token) => break Start::Resume(token),
+                Some(_) => Message::error("Unknown or expired resume token"),
+                None => match login(&state, protocol, &line) {
+                    Ok((username, password)) if state.accounts().is_registered(&username) => {
+                        match password {
+                            Some(password) => {
+                                match authenticate(&state, addr, &username, password).await {
+                                    Ok(()) => break Start::Login(username, true),
+                                    Err(e) => Message::error(e.to_string()),
+                                }
+                            }
+                            // the username and its password make a single attempt
+                            None => {
+                                pending = Some(username);
+                                attempts -= 1;
+                                continue;
+                            }
+                        }
+                    }
+                    Ok((username, _)) if state.claim(addr, &username) => {
+                        break Start::Login(username, false)
+                    }
+                    Ok((username, _)) => {
+                        Message::error(format!("Username {} is already taken", username))
+                    }
+                    Err(e) => Message::error(e.to_string()),
+                },
+            },
+        };
+        sink.send(protocol.encode(&Entry::new(reply))).await?;
+    };
+
+    let mut peer = match start {
+        Start::Login(username, registered) => {
+            let peer = state.add(addr, username, protocol, sink, stream).await;
+            if registered {
+                state.set_account(addr, &peer.username);
+            }
+            if !state.resume_grace().is_zero() {
+                state.send(addr, Message::resume(&peer.token));
+            }
+            join_room(&state, addr, &peer.username, DEFAULT_ROOM);
+            peer
+        }
+        // the room never saw the session leave, so it is not told about the return either
+        Start::Resume(token) => {
+            let peer = state.resume(&token, addr, protocol, sink, stream)?;
+            info!(peer = %addr, "{} resumed their session", peer.username);
+            state.send(addr, Message::system("Session resumed"));
+            peer
+        }
+    };
+
+    let mut limiter = Limiter::new(state.rate_limit(), Instant::now());
+    let keepalive = state.keepalive();
+    let mut last_seen = time::Instant::now();
+    let mut pinged = false;
+    // whether the connection dropped, rather than the server ending the session
+    let lost = loop {
+        let timer = async {
+            match keepalive.next(last_seen, pinged) {
+                Some((at, timeout)) => {
+                    time::sleep_until(at).await;
+                    timeout
+                }
+                None => future::pending().await,
+            }
+        };
+        let line = tokio::select! {
+            line = peer.stream.next() => line,
+            // evicted by the server, the writer task still flushes the notice
+            _ = peer.closed.cancelled() => break false,
+            // resumed on another connection, which owns the session now
+            _ = peer.detached.cancelled() => return Ok(()),
+            timeout = timer => match timeout {
+                Timeout::Ping => {
+                    pinged = true;
+                    state.send(addr, Message::Ping);
+                    continue;
+                }
+                Timeout::Idle => {
+                    info!(peer = %addr, "Disconnecting idle peer");
+                    state.send(addr, Message::error("Disconnected for being idle"));
+                    break false;
+                }
+            },
+        };
+        let line = match line {
+            None => break true,
+            Some(Ok(line)) => line,
+            Some(Err(e)) if e.is::<LineTooLong>() => {
+                state.send(addr, Message::error(format!("{:#}", e)));
+                break false;
+            }
+            Some(Err(e)) if e.is::<Closed>() => break false,
+            Some(Err(e)) => {
+                warn!("Failed to read line from {}: {:#}", addr, e);
+                break true;
+            }
+        };
+        last_seen = time::Instant::now();
+        pinged = false;
+
+        // pongs only keep the connection alive, they cost no rate limit token
+        let request = protocol.decode(&line);
+        if matches!(request, Ok(Request::Pong)) {
+            continue;
+        }
+        state.metrics().received();
+
+        match limiter.check(Instant::now()) {
+            Verdict::Allow => {}
+            Verdict::Muted => continue,
+            Verdict::Mute(duration) => {
+                warn!(peer = %addr, strikes = limiter.strikes(), "Muting flooding peer");
+                let text = format!("Slow down, you are muted for {}s", duration.as_secs());
+                state.send(addr, Message::error(text));
+                continue;
+            }
+            Verdict::Kick => {
+                warn!(peer = %addr, strikes = limiter.strikes(), "Disconnecting flooding peer");
+                state.send(addr, Message::error("Disconnected for flooding"));
+                break false;
+            }
+        }
+
+        match request {
+            Ok(Request::Command { command }) => match Command::parse(&command) {
+                Some(Ok(Command::Register(password))) => {
+                    let reply = register(&state, addr, &peer.username, password).await;
+                    state.send(addr, reply);
+                }
+                Some(Ok(Command::Plugin { .. })) if state.is_muted(&addr) => {
+                    state.send(addr, Message::error("You are muted"))
+                }
+                Some(Ok(Command::Plugin { name, args })) => {
+                    let ctx = plugin::Context::new(&peer.username, state.room_of(&addr));
+                    match state.plugins().on_command(&ctx, &name, &args).await {
+                        Some(Ok(actions)) => plugin::perform(&state, addr, &ctx, actions),
+                        Some(Err(e)) => state.send(addr, Message::error(format!("{:#}", e))),
+                        None => {
+                            state.send(addr, Message::error(format!("Unknown command: /{}", name)))
+                        }
+                    }
+                }
+                Some(Ok(cmd)) => handle_command(&state, addr, &mut peer.username, cmd),
+                Some(Err(e)) => state.send(addr, Message::error(e.to_string())),
+                None => state.send(addr, Message::error("Commands start with /")),
+            },
+            Ok(Request::Chat { .. }) if state.is_muted(&addr) => {
+                state.send(addr, Message::error("You are muted"))
+            }
+            Ok(Request::Chat { content }) => {
+                let Some(room) = state.room_of(&addr) else {
+                    let msg = Message::error("You are not in a room, /join one first");
+                    state.send(addr, msg);
+                    continue;
+                };
+
+                let ctx = plugin::Context::new(&peer.username, Some(room.clone()));
+                let actions = state.plugins().on_message(&ctx, &content).await;
+                if let Some(reason) = plugin::vetoed(&actions) {
+                    state.send(addr, Message::error(reason));
+                    continue;
+                }
+
+                let msg = Message::chat(&room, &peer.username, content.clone());
+                info!("{}", msg);
+                state.broadcast(&room, addr, msg);
+                plugin::perform(&state, addr, &ctx, actions);
+                plugin::posted(&state, addr, &ctx, content);
+            }
+            Ok(Request::Pong) => {}
+            Ok(Request::Login { .. } | Request::Resume { .. }) => {
+                state.send(addr, Message::error("You are already logged in"))
+            }
+            Err(e) => state.send(addr, Message::error(format!("{:#}", e))),
+        }
+    };
+
+    // hold on to the session in case the client comes back with its token
+    let grace = state.resume_grace();
+    if lost && !grace.is_zero() {
+        state.park(&addr);
+        info!(peer = %addr, "Holding session of {} for {:?}", peer.username, grace);
+        tokio::select! {
+            _ = time::sleep(grace) => {}
+            _ = peer.detached.cancelled() => return Ok(()),
+            // kicked, or the server is shutting down
+            _ = peer.closed.cancelled() => {}
+        }
+    }
+
+    // when loop ends, peer has left the chat or line reading failed
+    if let Some((username, Some(room))) = state.remove(&addr) {
+        let msg = Message::left(&room, username);
+        info!("{}", msg);
+        state.broadcast(&room, addr, msg);
+    }
+
+    Ok(())
+}
+
+pub(crate) fn line_too_long() -> anyhow::Error {
+    LineTooLong.into()
+}
+
+// the one read error caused by the client rather than the connection
+#[derive(Debug)]
+struct LineTooLong;
+
+impl std::fmt::Display for LineTooLong {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Line too long, the limit is {} bytes", MAX_LINE_LENGTH)
+    }
+}
+
+impl std::error::Error for LineTooLong {}
+
+// ends the session for good, for transports telling a client's goodbye from a lost connection
+#[derive(Debug)]
+pub(crate) struct Closed;
+
+impl std::fmt::Display for Closed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Closed by the client")
+    }
+}
+
+impl std::error::Error for Closed {}
+
+enum Start {
+    // username, and whether it is a registered one
+    Login(String, bool),
+    Resume(String),
+}
+
+// `RESUME <token>` from plain clients, a resume request from JSON ones
+fn resume_token(protocol: Protocol, line: &str) -> Option<String> {
+    match protocol.decode(line).ok()? {
+        Request::Resume { token } => Some(token),
+        Request::Chat { content } => {
+            let (keyword, token) = content.trim().split_once(char::is_whitespace)?;
+            keyword
+                .eq_ignore_ascii_case("resume")
+                .then(|| token.trim().to_string())
+        }
+        _ => None,
+    }
+}
+
+// validate the username of a login line, along with the password sent with it
+fn login(state: &State, protocol: Protocol, line: &str) -> Result<(String, Option<String>)> {
+    let (username, password) = match protocol.decode(line)? {
+        Request::Login { username, password } => (username, password),
+        Request::Chat { content } => (content, None),
+        Request::Command { .. } | Request::Pong | Request::Resume { .. } => {
+            anyhow::bail!("Log in before sending commands")
+        }
+    };
+
+    let username = command::validate_username(username.trim())?;
+    if state.bans().is_banned_user(username) {
+        anyhow::bail!("Username {} is banned", username);
+    }
+    Ok((username.to_string(), password))
+}
+
+// the answer to a password prompt, plain clients send it as is
+fn password(protocol: Protocol, line: &str) -> Result<String> {
+    match protocol {
+        Protocol::Plain => Ok(line.to_string()),
+        Protocol::Json => match protocol.decode(line)? {
+            Request::Chat { content } => Ok(content),
+            Request::Login {
+                password: Some(password),
+                ..
+            } => Ok(password),
+            _ => anyhow::bail!("Expected a password"),
+        },
+    }
+}
+
+// check the password of a registered username, then claim it
+async fn authenticate(
+    state: &Arc<State>,
+    addr: SocketAddr,
+    username: &str,
+    password: String,
+) -> Result<()> {
+    if let Some(wait) = state.accounts().backoff(addr.ip()) {
+        anyhow::bail!(
+            "Too many failed logins, try again in {}s",
+            wait.as_secs().max(1)
+        );
+    }
+
+    // hashing takes a while, keep it off the runtime threads
+    let permit = state.accounts().permit().await;
+    let verified = {
+        let state = state.clone();
+        let username = username.to_string();
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            state.accounts().verify(&username, &password)
+        })
+        .await?
+    };
+    if !verified {
+        warn!(peer = %addr, "Wrong password for {}", username);
+        state.accounts().failed(addr.ip());
+        anyhow::bail!("Wrong password for {}", username);
+    }
+    state.accounts().succeeded(addr.ip());
+    if !state.claim(addr, username) {
+        anyhow::bail!("Username {} is already taken", username);
+    }
+    Ok(())
+}
+
+async fn register(
+    state: &Arc<State>,
+    addr: SocketAddr,
+    username: &str,
+    password: String,
+) -> Message {
+    let permit = state.accounts().permit().await;
+    let registered = {
+        let state = state.clone();
+        let username = username.to_string();
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            state.accounts().register(&username, &password)
+        })
+        .await
+    };
+    match registered.map_err(anyhow::Error::from).and_then(|r| r) {
+        Ok(()) => {
+            info!(peer = %addr, "Registered {}", username);
+            state.set_account(addr, username);
+            Message::system(format!(
+                "Registered {}, log in with your password from now on",
+                username
+            ))
+        }
+        Err(e) => Message::error(e.to_string()),
+    }
+}
+
+fn handle_command(state: &Arc<State>, addr: SocketAddr, username: &mut String, cmd: Command) {
+    if cmd.needs_admin() && !state.is_admin(&addr) {
+        state.send(addr, Message::error("Permission denied, /admin first"));
+        return;
+    }
+
+    let reply = match cmd {
+        Command::Join(room) => {
+            join_room(state, addr, username, &room);
+            return;
+        }
+        Command::Leave => match state.room_of(&addr) {
+            Some(room) if room != DEFAULT_ROOM => {
+                join_room(state, addr, username, DEFAULT_ROOM);
+                return;
+            }
+            _ => Message::error(format!("You are already in {}", DEFAULT_ROOM)),
+        },
+        Command::Rooms => {
+            let rooms = state
+                .rooms()
+                .into_iter()
+                .map(|(room, count)| format!("{} ({})", room, count))
+                .collect::<Vec<_>>();
+            Message::system(format!("Rooms: {}", rooms.join(", ")))
+        }
+        Command::Who => match state.room_of(&addr) {
+            Some(room) => Message::system(format!("In {}: {}", room, state.who(&room).join(", "))),
+            None => Message::error("You are not in a room"),
+        },
+        Command::Nick(name) => match state.rename(addr, &name) {
+            Ok(old) => {
+                *username = name.clone();
+                if let Some(room) = state.room_of(&addr) {
+                    let msg = Message::renamed(&room, old, &name);
+                    info!("{}", msg);
+                    state.broadcast(&room, addr, msg);
+                }
+                Message::system(format!("You are now known as {}", name))
+            }
+            Err(e) => Message::error(e.to_string()),
+        },
+        Command::Msg { .. } if state.is_muted(&addr) => Message::error("You are muted"),
+        Command::Msg { to, content } => match state.lookup(&to) {
+            Some(target) => {
+                state.send(target, Message::private(username.as_str(), content));
+                return;
+            }
+            None => Message::error(format!("No such user: {}", to)),
+        },
+        Command::History(n) => match state.room_of(&addr) {
+            Some(room) => {
+                state.replay(addr, &room, n.min(state.history().capacity()));
+                return;
+            }
+            None => Message::error("You are not in a room"),
+        },
+        // handled by the session, hashing the password and plugins are async
+        Command::Register(_) | Command::Plugin { .. } => return,
+        Command::Admin(secret) => match state.authenticate(addr, &secret) {
+            Ok(()) => {
+                info!(peer = %addr, username = %username, "Granted admin role");
+                Message::system("You are now an admin")
+            }
+            Err(e) => {
+                warn!(peer = %addr, username = %username, "Failed admin login: {}", e);
+                Message::error(e.to_string())
+            }
+        },
+        Command::Kick(user) => match state.lookup(&user) {
+            Some(target) if target == addr => Message::error("You cannot kick yourself"),
+            Some(target) => {
+                info!(by = %username, "Kicking {}", user);
+                let notice = Message::error(format!("You were kicked by {}", username));
+                state.disconnect(target, notice);
+                Message::system(format!("Kicked {}", user))
+            }
+            None => Message::error(format!("No such user: {}", user)),
+        },
+        Command::Ban { target, duration } => ban(state, addr, username, &target, duration)
+            .unwrap_or_else(|e| Message::error(format!("{:#}", e))),
+        Command::Mute { user, duration } => match state.lookup(&user) {
+            Some(target) if target == addr => Message::error("You cannot mute yourself"),
+            Some(target) => {
+                let duration = duration.unwrap_or(command::DEFAULT_MUTE);
+                match state.mute(target, duration) {
+                    Ok(_) => {
+                        info!(by = %username, "Muting {} for {}s", user, duration.as_secs());
+                        let secs = duration.as_secs();
+                        let text = format!("You were muted by {} for {}s", username, secs);
+                        state.send(target, Message::error(text));
+                        Message::system(format!("Muted {} for {}s", user, secs))
+                    }
+                    Err(e) => Message::error(e.to_string()),
+                }
+            }
+            None => Message::error(format!("No such user: {}", user)),
+        },
+        Command::Announce(text) => {
+            info!(by = %username, "Announcement: {}", text);
+            state.announce(Message::system(format!("Announcement: {}", text)));
+            return;
+        }
+    };
+
+    state.send(addr, reply);
+}
+
+// persist a ban, then disconnect the peers it covers
+fn ban(
+    state: &State,
+    addr: SocketAddr,
+    username: &str,
+    target: &str,
+    duration: Option<Duration>,
+) -> Result<Message> {
+    let target = BanTarget::parse(target);
+    let until = match duration {
+        Some(duration) => Some(
+            chrono::Duration::from_std(duration)
+                .ok()
+                .and_then(|duration| Utc::now().checked_add_signed(duration))
+                .ok_or_else(|| anyhow!("Ban duration is too long"))?,
+        ),
+        None => None,
+    };
+
+    let peers = match &target {
+        BanTarget::User(name) => state.lookup(name).into_iter().collect(),
+        BanTarget::Ip(ip) => state.peers_from(*ip),
+    };
+    if peers.contains(&addr) {
+        return Err(anyhow!("You cannot ban yourself"));
+    }
+
+    state.bans().add(Ban {
+        target: target.clone(),
+        until,
+        by: username.to_string(),
+    })?;
+    info!(by = %username, "Banned {} until {:?}", target, until);
+
+    for peer in peers {
+        state.disconnect(
+            peer,
+            Message::error(format!("You were banned by {}", username)),
+        );
+    }
+    Ok(Message::system(match until {
+        Some(until) => format!(
+            "Banned {} until {}",
+            target,
+            until.format("%Y-%m-%d %H:%M UTC")
+        ),
+        None => format!("Banned {}", target),
+    }))
+}
+
+// move peer into `room`, telling the old and the new room about it
+fn join_room(state: &Arc<State>, addr: SocketAddr, username: &str, room: &str) {
+    if state.room_of(&addr).as_deref() == Some(room) {
+        state.send(addr, Message::error(format!("You are already in {}", room)));
+        return;
+    }
+
+    if let Some(prev) = state.join(addr, room) {
+        let msg = Message::left(prev.as_str(), username);
+        info!("{}", msg);
+        state.broadcast(&prev, addr, msg);
+    }
+
+    state.send(addr, Message::system(format!("You joined {}", room)));
+    state.replay(addr, room, command::DEFAULT_REPLAY);
+
+    let msg = Message::joined(room, username);
+    info!("{}", msg);
+    state.broadcast(room, addr, msg);
+    plugin::joined(state, addr, username, room);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::channel::mpsc;
+
+    // drive a session over in-memory channels, one string per line each way
+    pub(super) fn connect(
+        state: &Arc<State>,
+        port: u16,
+    ) -> (
+        mpsc::UnboundedSender<String>,
+        mpsc::UnboundedReceiver<String>,
+    ) {
+        let (client_tx, server_rx) = mpsc::unbounded::<String>();
+        let (server_tx, client_rx) = mpsc::unbounded::<String>();
+        let sink = Box::pin(server_tx.sink_map_err(anyhow::Error::from));
+        let stream = server_rx.map(Ok).boxed();
+        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+        tokio::spawn(handle_lines(state.clone(), addr, sink, stream));
+        (client_tx, client_rx)
+    }
+
+    #[tokio::test]
+    async fn json_client_should_talk_to_plain_client() -> Result<()> {
+        let state = Arc::new(State::default());
+
+        let (bot_tx, mut bot_rx) = connect(&state, 1001);
+        assert_eq!(bot_rx.next().await.unwrap(), "Enter your username: ");
+        bot_tx.unbounded_send("PROTO json".to_string())?;
+        let line = bot_rx.next().await.unwrap();
+        let entry: Entry = serde_json::from_str(&line)?;
+        assert_eq!(entry.message, Message::system("Protocol set to json"));
+        let prompt: Entry = serde_json::from_str(&bot_rx.next().await.unwrap())?;
+        assert_eq!(prompt.message, Message::system("Enter your username: "));
+        bot_tx.unbounded_send(r#"{"type":"login","username":"bot"}"#.to_string())?;
+        let token: Entry = serde_json::from_str(&bot_rx.next().await.unwrap())?;
+        assert!(matches!(token.message, Message::Resume { .. }));
+        let joined: Entry = serde_json::from_str(&bot_rx.next().await.unwrap())?;
+        assert_eq!(joined.message, Message::system("You joined lobby"));
+
+        let (alice_tx, mut alice_rx) = connect(&state, 1002);
+        alice_tx.unbounded_send("alice".to_string())?;
+        alice_tx.unbounded_send("hello bot".to_string())?;
+
+        let joined: Entry = serde_json::from_str(&bot_rx.next().await.unwrap())?;
+        assert_eq!(joined.message, Message::joined(DEFAULT_ROOM, "alice"));
+        let line = bot_rx.next().await.unwrap();
+        assert!(
+            line.contains(r#""type":"chat","room":"lobby","sender":"alice","content":"hello bot""#)
+        );
+
+        bot_tx.unbounded_send(r#"{"type":"chat","content":"hi alice"}"#.to_string())?;
+        bot_tx.unbounded_send(r#"{"type":"command","command":"/msg nobody x"}"#.to_string())?;
+        bot_tx.unbounded_send("not json".to_string())?;
+        let error: Entry = serde_json::from_str(&bot_rx.next().await.unwrap())?;
+        assert_eq!(error.message, Message::error("No such user: nobody"));
+        let error: Entry = serde_json::from_str(&bot_rx.next().await.unwrap())?;
+        assert!(matches!(error.message, Message::Error { .. }));
+
+        let mut alice_lines = Vec::new();
+        while alice_lines.last().map(String::as_str) != Some("[lobby] bot: hi alice") {
+            alice_lines.push(alice_rx.next().await.unwrap());
+        }
+        assert_eq!(alice_lines[0], "Enter your username: ");
+        assert!(alice_lines.contains(&"[lobby] bot joined the room".to_string()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn flooding_peer_should_be_muted_then_kicked() -> Result<()> {
+        let limit = RateLimit {
+            burst: 2,
+            per_second: 0.0,
+            mute_secs: 0,
+            strikes: 2,
+        };
+        let state = Arc::new(State::default().with_rate_limit(limit));
+
+        let (tx, mut rx) = connect(&state, 1001);
+        tx.unbounded_send("alice".to_string())?;
+        for i in 0..5 {
+            tx.unbounded_send(format!("/who {}", i))?;
+        }
+
+        let mut lines = Vec::new();
+        while let Some(line) = rx.next().await {
+            lines.push(line);
+        }
+        assert_eq!(
+            &lines[lines.len() - 2..],
+            [
+                "* Slow down, you are muted for 0s",
+                "* Disconnected for flooding"
+            ]
+        );
+        assert_eq!(state.peer_count(), 0);
+        Ok(())
+    }
+
+    // skip lines until `expected` shows up
+    pub(super) async fn wait_for(rx: &mut mpsc::UnboundedReceiver<String>, expected: &str) {
+        while let Some(line) = rx.next().await {
+            if line == expected {
+                return;
+            }
+        }
+        panic!("connection closed before {:?}", expected);
+    }
+
+    pub(super) async fn resume_token(rx: &mut mpsc::UnboundedReceiver<String>) -> String {
+        while let Some(line) = rx.next().await {
+            if let Some(token) = line.strip_prefix("* Resume token: ") {
+                return token.to_string();
+            }
+        }
+        panic!("connection closed before the resume token");
+    }
+
+    #[tokio::test]
+    async fn second_session_on_an_address_should_be_refused() -> Result<()> {
+        let state = Arc::new(State::default());
+        let (alice_tx, mut alice_rx) = connect(&state, 1001);
+        alice_tx.unbounded_send("alice".to_string())?;
+        wait_for(&mut alice_rx, "* You joined lobby").await;
+
+        // another listener saw the same remote address
+        let (_tx, mut rx) = connect(&state, 1001);
+        assert_eq!(
+            rx.next().await.unwrap(),
+            "* Address in use, reconnect and try again"
+        );
+        assert_eq!(rx.next().await, None);
+        alice_tx.unbounded_send("/who".to_string())?;
+        wait_for(&mut alice_rx, "* In lobby: alice").await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dropped_session_should_resume_quietly() -> Result<()> {
+        let state = Arc::new(State::default());
+
+        let (alice_tx, mut alice_rx) = connect(&state, 1001);
+        alice_tx.unbounded_send("alice".to_string())?;
+        let token = resume_token(&mut alice_rx).await;
+        let (bob_tx, mut bob_rx) = connect(&state, 1002);
+        bob_tx.unbounded_send("bob".to_string())?;
+        wait_for(&mut alice_rx, "[lobby] bob joined the room").await;
+
+        // the connection drops, messages sent meanwhile wait for the resume
+        drop(alice_tx);
+        assert_eq!(alice_rx.next().await, None);
+        bob_tx.unbounded_send("missed me?".to_string())?;
+        // still in the room while the session is held
+        bob_tx.unbounded_send("/who".to_string())?;
+        wait_for(&mut bob_rx, "* In lobby: alice, bob").await;
+
+        let (alice_tx, mut alice_rx) = connect(&state, 1003);
+        alice_tx.unbounded_send(format!("RESUME {}", token))?;
+        wait_for(&mut alice_rx, "[lobby] bob: missed me?").await;
+        wait_for(&mut alice_rx, "* Session resumed").await;
+
+        // a live connection is taken over too
+        let (again_tx, mut again_rx) = connect(&state, 1004);
+        again_tx.unbounded_send(format!("resume {}", token))?;
+        wait_for(&mut again_rx, "* Session resumed").await;
+        assert_eq!(alice_rx.next().await, None);
+
+        again_tx.unbounded_send("back".to_string())?;
+        // no leave or join in between
+        assert_eq!(bob_rx.next().await.unwrap(), "[lobby] alice: back");
+        assert_eq!(state.who(DEFAULT_ROOM), vec!["alice", "bob"]);
+        assert_eq!(
+            state.lookup("alice"),
+            Some(SocketAddr::from(([127, 0, 0, 1], 1004)))
+        );
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn session_should_expire_after_grace() -> Result<()> {
+        let state = Arc::new(State::default().with_resume_grace(Duration::from_secs(5)));
+
+        let (alice_tx, mut alice_rx) = connect(&state, 1001);
+        alice_tx.unbounded_send("alice".to_string())?;
+        let token = resume_token(&mut alice_rx).await;
+        let (bob_tx, mut bob_rx) = connect(&state, 1002);
+        bob_tx.unbounded_send("bob".to_string())?;
+        wait_for(&mut alice_rx, "[lobby] bob joined the room").await;
+
+        drop(alice_tx);
+        let start = time::Instant::now();
+        wait_for(&mut bob_rx, "[lobby] alice left the room").await;
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+
+        let (alice_tx, mut alice_rx) = connect(&state, 1003);
+        alice_tx.unbounded_send(format!("RESUME {}", token))?;
+        wait_for(&mut alice_rx, "* Unknown or expired resume token").await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn admin_should_mute_and_ban() -> Result<()> {
+        let state = Arc::new(State::default().with_admin_secret("hunter2"));
+
+        let (alice_tx, mut alice_rx) = connect(&state, 1001);
+        alice_tx.unbounded_send("alice".to_string())?;
+        wait_for(&mut alice_rx, "* You joined lobby").await;
+        let (bob_tx, mut bob_rx) = connect(&state, 1002);
+        bob_tx.unbounded_send("bob".to_string())?;
+        wait_for(&mut alice_rx, "[lobby] bob joined the room").await;
+
+        alice_tx.unbounded_send("/kick bob".to_string())?;
+        wait_for(&mut alice_rx, "* Permission denied, /admin first").await;
+        alice_tx.unbounded_send("/admin hunter3".to_string())?;
+        wait_for(&mut alice_rx, "* Wrong admin secret").await;
+        alice_tx.unbounded_send("/admin hunter2".to_string())?;
+        wait_for(&mut alice_rx, "* You are now an admin").await;
+
+        alice_tx.unbounded_send("/mute bob 18446744073709551615s".to_string())?;
+        wait_for(&mut alice_rx, "* Mute duration is too long").await;
+        alice_tx.unbounded_send("/mute bob 1m".to_string())?;
+        wait_for(&mut bob_rx, "* You were muted by alice for 60s").await;
+        bob_tx.unbounded_send("hi".to_string())?;
+        wait_for(&mut bob_rx, "* You are muted").await;
+
+        alice_tx.unbounded_send("/ban Bob".to_string())?;
+        wait_for(&mut alice_rx, "* Banned bob").await;
+        wait_for(&mut bob_rx, "* You were banned by alice").await;
+        assert_eq!(bob_rx.next().await, None);
+        wait_for(&mut alice_rx, "[lobby] bob left the room").await;
+
+        let (bob_tx, mut bob_rx) = connect(&state, 1003);
+        bob_tx.unbounded_send("bob".to_string())?;
+        wait_for(&mut bob_rx, "* Username bob is banned").await;
+        let (rob_tx, mut rob_rx) = connect(&state, 1004);
+        rob_tx.unbounded_send("rob".to_string())?;
+        wait_for(&mut rob_rx, "* You joined lobby").await;
+        rob_tx.unbounded_send("/nick Bob".to_string())?;
+        wait_for(&mut rob_rx, "* Username Bob is banned").await;
+        assert_eq!(state.lookup("bob"), None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn registered_name_should_need_password() -> Result<()> {
+        let state = Arc::new(State::default());
+
+        let (alice_tx, mut alice_rx) = connect(&state, 1001);
+        alice_tx.unbounded_send("alice".to_string())?;
+        alice_tx.unbounded_send("/register correct horse".to_string())?;
+        wait_for(
+            &mut alice_rx,
+            "* Registered alice, log in with your password from now on",
+        )
+        .await;
+        alice_tx.unbounded_send("/nick alicia".to_string())?;
+        wait_for(&mut alice_rx, "* You are now known as alicia").await;
+
+        // free to take while its owner uses another name, but only with the password
+        let (mallory_tx, mut mallory_rx) = connect(&state, 1002);
+        mallory_tx.unbounded_send("Alice".to_string())?;
+        wait_for(&mut mallory_rx, "Password for Alice: ").await;
+        mallory_tx.unbounded_send("hunter2".to_string())?;
+        wait_for(&mut mallory_rx, "* Wrong password for Alice").await;
+        mallory_tx.unbounded_send("mallory".to_string())?;
+        mallory_tx.unbounded_send("/nick alice".to_string())?;
+        wait_for(
+            &mut mallory_rx,
+            "* Username alice is registered to someone else",
+        )
+        .await;
+
+        // the owner can take the name back
+        alice_tx.unbounded_send("/nick Alice".to_string())?;
+        wait_for(&mut alice_rx, "* You are now known as Alice").await;
+
+        let (bot_tx, mut bot_rx) = connect(&state, 1003);
+        bot_tx.unbounded_send("PROTO json".to_string())?;
+        bot_tx.unbounded_send(
+            r#"{"type":"login","username":"alice","password":"correct horse"}"#.to_string(),
+        )?;
+        assert_eq!(bot_rx.next().await.unwrap(), "Enter your username: ");
+        let mut lines = Vec::new();
+        for _ in 0..3 {
+            let entry: Entry = serde_json::from_str(&bot_rx.next().await.unwrap())?;
+            lines.push(entry.message);
+        }
+        // right password, but the owner is online under that name
+        assert_eq!(lines[2], Message::error("Username alice is already taken"));
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn silent_peer_should_be_pinged_then_dropped() -> Result<()> {
+        let keepalive = Keepalive {
+            idle_secs: 30,
+            ping_secs: 10,
+        };
+        let state = Arc::new(State::default().with_keepalive(keepalive));
+
+        let (tx, mut rx) = connect(&state, 1001);
+        tx.unbounded_send("alice".to_string())?;
+        wait_for(&mut rx, "* You joined lobby").await;
+
+        let start = time::Instant::now();
+        assert_eq!(rx.next().await.unwrap(), "PING");
+        assert_eq!(start.elapsed(), Duration::from_secs(10));
+        tx.unbounded_send("PONG".to_string())?;
+
+        assert_eq!(rx.next().await.unwrap(), "PING");
+        assert_eq!(rx.next().await.unwrap(), "* Disconnected for being idle");
+        assert_eq!(rx.next().await, None);
+        assert_eq!(start.elapsed(), Duration::from_secs(40));
+        assert_eq!(state.peer_count(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shutdown_should_notify_peers_and_stop_accepting() -> Result<()> {
+        let state = Arc::new(State::default());
+        let server = tokio::spawn(serve_with("127.0.0.1:0", state.clone()));
+
+        let (alice_tx, mut alice_rx) = connect(&state, 1001);
+        alice_tx.unbounded_send("alice".to_string())?;
+        wait_for(&mut alice_rx, "* You joined lobby").await;
+        let (_bob_tx, mut bob_rx) = connect(&state, 1002);
+        assert_eq!(bob_rx.next().await.unwrap(), "Enter your username: ");
+
+        state.shutdown(SHUTDOWN_DEADLINE).await;
+        wait_for(&mut alice_rx, "* Server shutting down").await;
+        assert_eq!(alice_rx.next().await, None);
+        // still logging in
+        assert_eq!(bob_rx.next().await.unwrap(), "* Server shutting down");
+        assert_eq!(bob_rx.next().await, None);
+
+        server.await??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn long_line_should_be_rejected() -> Result<()> {
+        let state = Arc::new(State::default());
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            let (stream, addr) = listener.accept().await.unwrap();
+            handle_client(state, addr, stream).await
+        });
+
+        let mut client = Framed::new(TcpStream::connect(addr).await?, LinesCodec::new());
+        assert_eq!(client.next().await.unwrap()?, "Enter your username: ");
+        client.send("a".repeat(MAX_LINE_LENGTH + 1)).await?;
+        assert_eq!(
+            client.next().await.unwrap()?,
+            "* Line too long, the limit is 1024 bytes"
+        );
+        assert!(client.next().await.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+use serde::Deserialize;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+use super::{Entry, Message};
+
+const EVICTED_NOTICE: &str = "Disconnected: you are too slow to keep up";
+
+// what to do when a peer's queue is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Overflow {
+    // make room by dropping the oldest queued message
+    #[default]
+    DropOldest,
+    // drop the incoming message
+    DropMessage,
+    // tell the peer and close its connection
+    Disconnect,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Push {
+    // carries the queue length, the new message included
+    Queued(usize),
+    Dropped,
+    // carries how many messages were lost, including the incoming one
+    Evicted(u64),
+    Closed,
+}
+
+// bounded per-peer queue, pushing never waits on a slow reader
+#[derive(Debug)]
+pub(crate) struct Outbox {
+    queue: Mutex<Queue>,
+    notify: Notify,
+    capacity: usize,
+    dropped: AtomicU64,
+    closed: CancellationToken,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    messages: VecDeque<Arc<Entry>>,
+    closed: bool,
+}
+
+impl Outbox {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            queue: Mutex::new(Queue::default()),
+            notify: Notify::new(),
+            capacity,
+            dropped: AtomicU64::new(0),
+            closed: CancellationToken::new(),
+        }
+    }
+
+    pub fn push(&self, entry: Arc<Entry>, overflow: Overflow) -> Push {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.closed {
+            return Push::Closed;
+        }
+
+        let ret = if queue.messages.len() < self.capacity {
+            queue.messages.push_back(entry);
+            Push::Queued(queue.messages.len())
+        } else {
+            match overflow {
+                Overflow::DropOldest => {
+                    queue.messages.pop_front();
+                    queue.messages.push_back(entry);
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    Push::Dropped
+                }
+                Overflow::DropMessage => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return Push::Dropped;
+                }
+                Overflow::Disconnect => {
+                    // everything still queued is lost, the notice is the last thing sent
+                    let lost = queue.messages.len() as u64 + 1;
+                    self.dropped.fetch_add(lost, Ordering::Relaxed);
+                    queue.messages.clear();
+                    let notice = Entry::new(Message::error(EVICTED_NOTICE));
+                    queue.messages.push_back(Arc::new(notice));
+                    queue.closed = true;
+                    self.closed.cancel();
+                    Push::Evicted(lost)
+                }
+            }
+        };
+        drop(queue);
+
+        self.notify.notify_one();
+        ret
+    }
+
+    // wait for the next message, `None` once closed and drained
+    pub async fn recv(&self) -> Option<Arc<Entry>> {
+        loop {
+            {
+                let mut queue = self.queue.lock().unwrap();
+                if let Some(entry) = queue.messages.pop_front() {
+                    return Some(entry);
+                }
+                if queue.closed {
+                    return None;
+                }
+            }
+            // a single reader, so a notify_one sent before we wait is kept as a permit
+            self.notify.notified().await;
+        }
+    }
+
+    pub fn close(&self) {
+        self.queue.lock().unwrap().closed = true;
+        self.closed.cancel();
+        self.notify.notify_one();
+    }
+
+    // queue a last notice past capacity and close, false if already closed
+    pub fn close_with(&self, entry: Arc<Entry>) -> bool {
+        {
+            let mut queue = self.queue.lock().unwrap();
+            if queue.closed {
+                return false;
+            }
+            queue.messages.push_back(entry);
+            queue.closed = true;
+        }
+        self.closed.cancel();
+        self.notify.notify_one();
+        true
+    }
+
+    // resolves once the outbox is closed, e.g. when the peer got evicted
+    pub fn closed(&self) -> CancellationToken {
+        self.closed.clone()
+    }
+
+    #[cfg(test)]
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.queue.lock().unwrap().messages.len()
+    }
+
+    #[cfg(test)]
+    pub fn try_recv(&self) -> Option<Arc<Entry>> {
+        self.queue.lock().unwrap().messages.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(i: usize) -> Arc<Entry> {
+        Arc::new(Entry::new(Message::chat("lobby", "alice", i.to_string())))
+    }
+
+    fn content(entry: Option<Arc<Entry>>) -> Option<String> {
+        match &entry?.message {
+            Message::Chat { content, .. } => Some(content.clone()),
+            msg => panic!("unexpected {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn drop_oldest_should_keep_latest() {
+        let outbox = Outbox::new(2);
+        assert_eq!(outbox.push(msg(1), Overflow::DropOldest), Push::Queued(1));
+        assert_eq!(outbox.push(msg(2), Overflow::DropOldest), Push::Queued(2));
+        assert_eq!(outbox.push(msg(3), Overflow::DropOldest), Push::Dropped);
+
+        assert_eq!(content(outbox.try_recv()), Some("2".to_string()));
+        assert_eq!(content(outbox.try_recv()), Some("3".to_string()));
+        assert_eq!(outbox.dropped(), 1);
+    }
+
+    #[test]
+    fn drop_message_should_keep_queued() {
+        let outbox = Outbox::new(1);
+        assert_eq!(outbox.push(msg(1), Overflow::DropMessage), Push::Queued(1));
+        assert_eq!(outbox.push(msg(2), Overflow::DropMessage), Push::Dropped);
+
+        assert_eq!(content(outbox.try_recv()), Some("1".to_string()));
+        assert_eq!(content(outbox.try_recv()), None);
+        assert_eq!(outbox.dropped(), 1);
+    }
+
+    #[tokio::test]
+    async fn disconnect_should_send_notice_then_close() {
+        let outbox = Outbox::new(1);
+        assert_eq!(outbox.push(msg(1), Overflow::Disconnect), Push::Queued(1));
+        assert_eq!(outbox.push(msg(2), Overflow::Disconnect), Push::Evicted(2));
+        assert_eq!(outbox.push(msg(3), Overflow::Disconnect), Push::Closed);
+        assert!(outbox.closed().is_cancelled());
+
+        assert_eq!(
+            outbox.recv().await.map(|entry| entry.message.clone()),
+            Some(Message::error(EVICTED_NOTICE))
+        );
+        assert_eq!(outbox.recv().await, None);
+        assert_eq!(outbox.dropped(), 2);
+    }
+}
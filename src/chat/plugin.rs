@@ -0,0 +1,381 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::{fmt, future::Future, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::time;
+use tracing::warn;
+
+use super::{Message, State};
+
+// how long a hook may take before its plugin is skipped
+pub const DEFAULT_PLUGIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+// who triggered a hook, and where
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Context {
+    pub username: String,
+    pub room: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    // a private message to the peer that triggered the hook
+    Reply(String),
+    // a chat message from the plugin to the peer's room
+    Broadcast(String),
+    // drop the peer's chat message, telling them why; ignored outside `on_message`
+    Veto(String),
+    // the action, once the delay has passed
+    After(Duration, Box<Action>),
+}
+
+// a bot living in the server, every hook has a default so a plugin implements only what it needs
+#[async_trait]
+pub trait Plugin: Send + Sync {
+    // shown as the sender of whatever the plugin says
+    fn name(&self) -> &str;
+
+    // slash commands handed to `on_command`, without the slash
+    fn commands(&self) -> &[&str] {
+        &[]
+    }
+
+    // the peer just joined `ctx.room`
+    async fn on_join(&self, _ctx: &Context) -> Result<Vec<Action>> {
+        Ok(Vec::new())
+    }
+
+    // before the peer's chat message goes out, it is dropped if any plugin vetoes it
+    async fn on_message(&self, _ctx: &Context, _content: &str) -> Result<Vec<Action>> {
+        Ok(Vec::new())
+    }
+
+    // after the peer's chat message went out, in the background; for slow work like fetching
+    // a page, which must not hold the chat up
+    async fn on_posted(&self, _ctx: &Context, _content: &str) -> Result<Vec<Action>> {
+        Ok(Vec::new())
+    }
+
+    // one of `commands`, an error is shown to the peer
+    async fn on_command(&self, _ctx: &Context, _name: &str, _args: &str) -> Result<Vec<Action>> {
+        Ok(Vec::new())
+    }
+}
+
+// the plugins registered at startup, run in order
+#[derive(Clone)]
+pub struct Plugins {
+    plugins: Vec<Arc<dyn Plugin>>,
+    timeout: Duration,
+}
+
+// actions along with the plugin they came from
+pub(crate) type Actions = Vec<(String, Action)>;
+
+impl fmt::Debug for Plugins {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Plugins")
+            .field(
+                "plugins",
+                &self.plugins.iter().map(|p| p.name()).collect::<Vec<_>>(),
+            )
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl Default for Plugins {
+    fn default() -> Self {
+        Self::new(DEFAULT_PLUGIN_TIMEOUT)
+    }
+}
+
+impl Context {
+    pub fn new(username: impl Into<String>, room: Option<String>) -> Self {
+        Self {
+            username: username.into(),
+            room,
+        }
+    }
+}
+
+impl Plugins {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            plugins: Vec::new(),
+            timeout,
+        }
+    }
+
+    pub fn with_plugin(mut self, plugin: Arc<dyn Plugin>) -> Self {
+        self.plugins.push(plugin);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    pub(crate) async fn on_join(&self, ctx: &Context) -> Actions {
+        let mut actions = Vec::new();
+        for plugin in &self.plugins {
+            let hook = plugin.on_join(ctx);
+            actions.extend(self.guard(plugin.name(), "on_join", hook).await);
+        }
+        actions
+    }
+
+    pub(crate) async fn on_message(&self, ctx: &Context, content: &str) -> Actions {
+        let mut actions = Vec::new();
+        for plugin in &self.plugins {
+            let hook = plugin.on_message(ctx, content);
+            actions.extend(self.guard(plugin.name(), "on_message", hook).await);
+        }
+        actions
+    }
+
+    pub(crate) async fn on_posted(&self, ctx: &Context, content: &str) -> Actions {
+        let mut actions = Vec::new();
+        for plugin in &self.plugins {
+            let hook = plugin.on_posted(ctx, content);
+            actions.extend(self.guard(plugin.name(), "on_posted", hook).await);
+        }
+        actions
+    }
+
+    // `None` when no plugin has the command
+    pub(crate) async fn on_command(
+        &self,
+        ctx: &Context,
+        name: &str,
+        args: &str,
+    ) -> Option<Result<Actions>> {
+        let plugin = self
+            .plugins
+            .iter()
+            .find(|plugin| plugin.commands().contains(&name))?;
+        let result = match time::timeout(self.timeout, plugin.on_command(ctx, name, args)).await {
+            Ok(Ok(actions)) => Ok(tag(plugin.name(), actions)),
+            Ok(Err(e)) => Err(e),
+            Err(_) => {
+                warn!(plugin = plugin.name(), "Command /{} timed out", name);
+                Err(anyhow!("/{} took too long, try again later", name))
+            }
+        };
+        Some(result)
+    }
+
+    // a failing or slow plugin is logged and skipped, the chat goes on without it
+    async fn guard(
+        &self,
+        plugin: &str,
+        hook: &str,
+        run: impl Future<Output = Result<Vec<Action>>>,
+    ) -> Actions {
+        match time::timeout(self.timeout, run).await {
+            Ok(Ok(actions)) => tag(plugin, actions),
+            Ok(Err(e)) => {
+                warn!(plugin, "Hook {} failed: {:#}", hook, e);
+                Vec::new()
+            }
+            Err(_) => {
+                warn!(plugin, "Hook {} timed out after {:?}", hook, self.timeout);
+                Vec::new()
+            }
+        }
+    }
+}
+
+fn tag(plugin: &str, actions: Vec<Action>) -> Actions {
+    actions
+        .into_iter()
+        .map(|action| (plugin.to_string(), action))
+        .collect()
+}
+
+// the first veto among `actions`, if any
+pub(crate) fn vetoed(actions: &Actions) -> Option<String> {
+    actions.iter().find_map(|(plugin, action)| match action {
+        Action::Veto(reason) => Some(format!("{}: {}", plugin, reason)),
+        _ => None,
+    })
+}
+
+// carry out what plugins asked for on behalf of the peer at `addr`
+pub(crate) fn perform(state: &Arc<State>, addr: SocketAddr, ctx: &Context, actions: Actions) {
+    for (plugin, action) in actions {
+        match action {
+            Action::Reply(text) => state.send(addr, Message::private(plugin, text)),
+            Action::Broadcast(text) => match &ctx.room {
+                Some(room) => state.post(room, Message::chat(room, plugin, text)),
+                None => state.send(addr, Message::private(plugin, text)),
+            },
+            Action::Veto(_) => {}
+            Action::After(delay, action) => {
+                // the session may have resumed on another connection by then, or be gone
+                let Some(token) = state.token_of(&addr) else {
+                    continue;
+                };
+                let (state, ctx) = (state.clone(), ctx.clone());
+                tokio::spawn(async move {
+                    tokio::select! {
+                        _ = time::sleep(delay) => {
+                            if let Some(addr) = state.session_addr(&token) {
+                                perform(&state, addr, &ctx, vec![(plugin, *action)]);
+                            }
+                        }
+                        _ = state.closing() => {}
+                    }
+                });
+            }
+        }
+    }
+}
+
+// on_join runs in the background, nothing waits on it
+pub(crate) fn joined(state: &Arc<State>, addr: SocketAddr, username: &str, room: &str) {
+    if state.plugins().is_empty() {
+        return;
+    }
+    let state = state.clone();
+    let ctx = Context::new(username, Some(room.to_string()));
+    tokio::spawn(async move {
+        let actions = state.plugins().on_join(&ctx).await;
+        perform(&state, addr, &ctx, actions);
+    });
+}
+
+// on_posted runs in the background too, after the message went out
+pub(crate) fn posted(state: &Arc<State>, addr: SocketAddr, ctx: &Context, content: String) {
+    if state.plugins().is_empty() {
+        return;
+    }
+    let (state, ctx) = (state.clone(), ctx.clone());
+    tokio::spawn(async move {
+        let actions = state.plugins().on_posted(&ctx, &content).await;
+        perform(&state, addr, &ctx, actions);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::tests::{connect, resume_token, wait_for};
+    use futures::StreamExt;
+
+    struct Censor;
+
+    #[async_trait]
+    impl Plugin for Censor {
+        fn name(&self) -> &str {
+            "censor"
+        }
+
+        fn commands(&self) -> &[&str] {
+            &["echo", "later"]
+        }
+
+        async fn on_join(&self, ctx: &Context) -> Result<Vec<Action>> {
+            Ok(vec![Action::Reply(format!("Welcome {}", ctx.username))])
+        }
+
+        async fn on_message(&self, _ctx: &Context, content: &str) -> Result<Vec<Action>> {
+            if content.contains("darn") {
+                return Ok(vec![Action::Veto("Mind your language".to_string())]);
+            }
+            Ok(Vec::new())
+        }
+
+        async fn on_posted(&self, _ctx: &Context, content: &str) -> Result<Vec<Action>> {
+            Ok(vec![Action::Reply(format!("Thanks for {}", content))])
+        }
+
+        async fn on_command(&self, _ctx: &Context, name: &str, args: &str) -> Result<Vec<Action>> {
+            match name {
+                "later" => {
+                    let reply = Action::Reply(args.to_string());
+                    Ok(vec![Action::After(
+                        Duration::from_millis(100),
+                        Box::new(reply),
+                    )])
+                }
+                _ => Ok(vec![Action::Broadcast(args.to_string())]),
+            }
+        }
+    }
+
+    // takes far longer than it may
+    struct Stuck;
+
+    #[async_trait]
+    impl Plugin for Stuck {
+        fn name(&self) -> &str {
+            "stuck"
+        }
+
+        fn commands(&self) -> &[&str] {
+            &["wait"]
+        }
+
+        async fn on_message(&self, _ctx: &Context, _content: &str) -> Result<Vec<Action>> {
+            time::sleep(Duration::from_secs(60)).await;
+            Ok(vec![Action::Veto("never".to_string())])
+        }
+
+        async fn on_command(&self, _: &Context, _: &str, _: &str) -> Result<Vec<Action>> {
+            time::sleep(Duration::from_secs(60)).await;
+            Ok(Vec::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn plugins_should_hook_into_the_chat() -> Result<()> {
+        let plugins = Plugins::new(Duration::from_millis(50))
+            .with_plugin(Arc::new(Censor))
+            .with_plugin(Arc::new(Stuck));
+        let state = Arc::new(State::default().with_plugins(plugins));
+
+        let (alice_tx, mut alice_rx) = connect(&state, 1001);
+        alice_tx.unbounded_send("alice".to_string())?;
+        wait_for(&mut alice_rx, "[pm] censor: Welcome alice").await;
+        let (bob_tx, mut bob_rx) = connect(&state, 1002);
+        bob_tx.unbounded_send("bob".to_string())?;
+        wait_for(&mut bob_rx, "[pm] censor: Welcome bob").await;
+
+        alice_tx.unbounded_send("darn it".to_string())?;
+        wait_for(&mut alice_rx, "* censor: Mind your language").await;
+        // the stuck plugin timed out, so the message goes through
+        alice_tx.unbounded_send("hello".to_string())?;
+        assert_eq!(bob_rx.next().await.unwrap(), "[lobby] alice: hello");
+        wait_for(&mut alice_rx, "[pm] censor: Thanks for hello").await;
+
+        alice_tx.unbounded_send("/echo hi all".to_string())?;
+        wait_for(&mut alice_rx, "[lobby] censor: hi all").await;
+        wait_for(&mut bob_rx, "[lobby] censor: hi all").await;
+        alice_tx.unbounded_send("/wait".to_string())?;
+        wait_for(&mut alice_rx, "* /wait took too long, try again later").await;
+        alice_tx.unbounded_send("/dance".to_string())?;
+        wait_for(&mut alice_rx, "* Unknown command: /dance").await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delayed_actions_should_follow_the_session() -> Result<()> {
+        let plugins = Plugins::default().with_plugin(Arc::new(Censor));
+        let state = Arc::new(State::default().with_plugins(plugins));
+
+        let (alice_tx, mut alice_rx) = connect(&state, 1001);
+        alice_tx.unbounded_send("alice".to_string())?;
+        let token = resume_token(&mut alice_rx).await;
+        alice_tx.unbounded_send("/later back soon".to_string())?;
+        wait_for(&mut alice_rx, "[pm] censor: Welcome alice").await;
+
+        // the connection drops and the session resumes from another address
+        drop(alice_tx);
+        assert_eq!(alice_rx.next().await, None);
+        let (alice_tx, mut alice_rx) = connect(&state, 1002);
+        alice_tx.unbounded_send(format!("RESUME {}", token))?;
+        wait_for(&mut alice_rx, "* Session resumed").await;
+        wait_for(&mut alice_rx, "[pm] censor: back soon").await;
+        Ok(())
+    }
+}
@@ -0,0 +1,223 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{Entry, Message};
+
+// how a client's lines are read and written, picked with a `PROTO <name>` line before login
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Protocol {
+    // free-form lines in, `Message` display text out
+    #[default]
+    Plain,
+    // one JSON object per line both ways, see `Request` and `Entry`
+    Json,
+}
+
+// what a client asks for, whatever the protocol
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    Login {
+        username: String,
+        // for registered names, the server prompts for it when missing
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        password: Option<String>,
+    },
+    Chat {
+        content: String,
+    },
+    Command {
+        command: String,
+    },
+    // answer to a `Message::Ping`
+    Pong,
+    // take a session over after reconnecting, instead of logging in
+    Resume {
+        token: String,
+    },
+}
+
+impl Protocol {
+    // `Some` when the line is a protocol switch rather than a login
+    pub fn negotiate(line: &str) -> Option<Result<Self>> {
+        let (keyword, name) = line.trim().split_once(char::is_whitespace)?;
+        if !keyword.eq_ignore_ascii_case("proto") {
+            return None;
+        }
+
+        Some(match name.trim().to_ascii_lowercase().as_str() {
+            "plain" => Ok(Protocol::Plain),
+            "json" => Ok(Protocol::Json),
+            _ => Err(anyhow!("Unknown protocol: {}", name.trim())),
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Protocol::Plain => "plain",
+            Protocol::Json => "json",
+        }
+    }
+
+    pub fn encode(&self, entry: &Entry) -> String {
+        match self {
+            Protocol::Plain => entry.message.to_string(),
+            // a Message with string fields and a timestamp always serializes
+            Protocol::Json => serde_json::to_string(entry).unwrap_or_default(),
+        }
+    }
+
+    // a bare line from the server, like the login prompt; plain clients get it verbatim
+    pub fn prompt(&self, text: &str) -> String {
+        match self {
+            Protocol::Plain => text.to_string(),
+            Protocol::Json => self.encode(&Entry::new(Message::system(text))),
+        }
+    }
+
+    pub fn decode(&self, line: &str) -> Result<Request> {
+        let request = self.parse(line)?;
+        match &request {
+            Request::Chat { content: text }
+            | Request::Command { command: text }
+            | Request::Login { username: text, .. } => check_text(text)?,
+            Request::Pong | Request::Resume { .. } => {}
+        }
+        Ok(request)
+    }
+
+    fn parse(&self, line: &str) -> Result<Request> {
+        match self {
+            Protocol::Plain if line.trim().eq_ignore_ascii_case("pong") => Ok(Request::Pong),
+            Protocol::Plain if line.trim_start().starts_with('/') => Ok(Request::Command {
+                command: line.to_string(),
+            }),
+            Protocol::Plain => Ok(Request::Chat {
+                content: line.to_string(),
+            }),
+            Protocol::Json => {
+                let request =
+                    serde_json::from_str::<Request>(line).context("Invalid JSON request")?;
+                match &request {
+                    Request::Chat { content } if content.trim_start().starts_with('/') => {
+                        Err(anyhow!("Send commands as {{\"type\":\"command\"}}"))
+                    }
+                    Request::Command { command } if !command.trim_start().starts_with('/') => {
+                        Err(anyhow!("Commands start with /"))
+                    }
+                    _ => Ok(request),
+                }
+            }
+        }
+    }
+}
+
+// a line break or escape sequence sent to plain clients as is would let the sender forge lines
+// of their own, say another user's message
+fn check_text(text: &str) -> Result<()> {
+    if text.chars().any(|c| c.is_control() && c != '\t') {
+        return Err(anyhow!("Control characters are not allowed"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn negotiate_should_switch_protocol() {
+        assert_eq!(
+            Protocol::negotiate("PROTO json").unwrap().unwrap(),
+            Protocol::Json
+        );
+        assert_eq!(
+            Protocol::negotiate("proto Plain").unwrap().unwrap(),
+            Protocol::Plain
+        );
+        assert!(Protocol::negotiate("PROTO xml").unwrap().is_err());
+        assert!(Protocol::negotiate("alice").is_none());
+        assert!(Protocol::negotiate("protocol json").is_none());
+    }
+
+    #[test]
+    fn json_should_encode_tagged_entry() {
+        let entry = Entry {
+            at: Utc.with_ymd_and_hms(2024, 10, 1, 12, 0, 0).unwrap(),
+            message: Message::chat("rust", "alice", "hi"),
+        };
+        assert_eq!(
+            Protocol::Json.encode(&entry),
+            r#"{"at":"2024-10-01T12:00:00Z","type":"chat","room":"rust","sender":"alice","content":"hi"}"#
+        );
+        assert_eq!(Protocol::Plain.encode(&entry), "[rust] alice: hi");
+
+        let entry = Entry {
+            message: Message::error("No such user: bob"),
+            ..entry
+        };
+        assert_eq!(
+            Protocol::Json.encode(&entry),
+            r#"{"at":"2024-10-01T12:00:00Z","type":"error","text":"No such user: bob"}"#
+        );
+    }
+
+    #[test]
+    fn decode_should_read_requests() {
+        assert_eq!(
+            Protocol::Plain.decode("/join rust").unwrap(),
+            Request::Command {
+                command: "/join rust".to_string()
+            }
+        );
+        assert_eq!(
+            Protocol::Json
+                .decode(r#"{"type":"chat","content":"hi"}"#)
+                .unwrap(),
+            Request::Chat {
+                content: "hi".to_string()
+            }
+        );
+        assert_eq!(
+            Protocol::Json
+                .decode(r#"{"type":"login","username":"bot"}"#)
+                .unwrap(),
+            Request::Login {
+                username: "bot".to_string(),
+                password: None
+            }
+        );
+        assert_eq!(Protocol::Plain.decode("PONG").unwrap(), Request::Pong);
+        assert_eq!(
+            Protocol::Json.decode(r#"{"type":"pong"}"#).unwrap(),
+            Request::Pong
+        );
+        assert!(Protocol::Json.decode("hi").is_err());
+        assert!(Protocol::Json
+            .decode(r#"{"type":"chat","content":"/join rust"}"#)
+            .is_err());
+        assert!(Protocol::Json
+            .decode(r#"{"type":"command","command":"join rust"}"#)
+            .is_err());
+    }
+
+    #[test]
+    fn decode_should_reject_control_characters() {
+        for line in [
+            r#"{"type":"chat","content":"hi\n[lobby] admin: bye"}"#,
+            r#"{"type":"chat","content":"hi\r* Announcement: bye"}"#,
+            r#"{"type":"command","command":"/msg bob hi\n* fake"}"#,
+            r#"{"type":"login","username":"bob\n[lobby] admin: hi"}"#,
+        ] {
+            assert!(Protocol::Json.decode(line).is_err(), "{}", line);
+        }
+        assert!(Protocol::Plain.decode("hi\x1b[2J").is_err());
+        assert_eq!(
+            Protocol::Plain.decode("tab\tseparated").unwrap(),
+            Request::Chat {
+                content: "tab\tseparated".to_string()
+            }
+        );
+    }
+}
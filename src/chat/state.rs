@@ -0,0 +1,1170 @@
+use anyhow::{anyhow, Result};
+use dashmap::{mapref::entry, DashMap, DashSet};
+use futures::{stream::BoxStream, Sink, SinkExt};
+use std::{
+    collections::BTreeSet,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio_util::{
+    sync::{CancellationToken, WaitForCancellationFuture},
+    task::TaskTracker,
+};
+use tracing::{info, warn};
+
+use super::{
+    outbox::{Outbox, Push},
+    Accounts, Bans, ChatLog, ChatMetrics, Entry, Federation, History, Keepalive, Message, Overflow,
+    Plugins, Protocol, RateLimit,
+};
+
+const MAX_MESSAGE: usize = 128;
+
+// how long a dropped connection's session waits for `RESUME <token>`
+pub const DEFAULT_RESUME_GRACE: Duration = Duration::from_secs(60);
+
+pub const DEFAULT_ROOM: &str = "lobby";
+
+// a client connection seen as lines of text, whatever the transport
+pub type LineStream = BoxStream<'static, Result<String>>;
+pub type LineSink = Pin<Box<dyn Sink<String, Error = anyhow::Error> + Send>>;
+
+#[derive(Debug)]
+pub struct State {
+    pub(crate) peers: DashMap<SocketAddr, PeerHandle>,
+    // room name to member addresses, a room exists only while it has members
+    rooms: DashMap<String, BTreeSet<SocketAddr>>,
+    // lowercased username to address, keeps names unique regardless of case
+    names: DashMap<String, SocketAddr>,
+    // resume token to the address of its session
+    sessions: DashMap<String, SocketAddr>,
+    // remote addresses with a session running, from any listener; sessions are told apart by
+    // address, and two listeners can see the same one
+    connections: DashSet<SocketAddr>,
+    resume_grace: Duration,
+    history: History,
+    log: Option<ChatLog>,
+    overflow: Overflow,
+    rate_limit: RateLimit,
+    keepalive: Keepalive,
+    // blake3 hash of the secret granting the admin role, `None` disables it
+    admin_secret: Option<blake3::Hash>,
+    bans: Bans,
+    accounts: Accounts,
+    // cancelled once the server starts shutting down
+    closing: CancellationToken,
+    // per-peer writer tasks, so shutdown can wait for them to flush
+    writers: TaskTracker,
+    metrics: ChatMetrics,
+    // linked servers and their users
+    federation: Federation,
+    plugins: Plugins,
+    capacity: usize,
+    dropped: AtomicU64,
+    evicted: AtomicU64,
+}
+
+#[derive(Debug)]
+pub(crate) struct PeerHandle {
+    username: String,
+    room: Option<String>,
+    outbox: Arc<Outbox>,
+    admin: bool,
+    muted_until: Option<Instant>,
+    // lowercased registered name the peer logged in as, it may use that name
+    account: Option<String>,
+    token: String,
+    conn: Connection,
+}
+
+// the current connection of a session, replaced when the session is resumed
+#[derive(Debug, Clone)]
+struct Connection {
+    // cancelled when another connection takes the session over
+    detach: CancellationToken,
+    // stops the writer only, the queue is kept for the next connection
+    writer: CancellationToken,
+}
+
+pub struct Peer {
+    pub username: String,
+    pub stream: LineStream,
+    // sent to the client so it can resume the session after losing its connection
+    pub token: String,
+    // cancelled when the server drops the peer, e.g. for being too slow
+    pub(crate) closed: CancellationToken,
+    // cancelled when the session was resumed on another connection
+    pub(crate) detached: CancellationToken,
+}
+
+impl fmt::Debug for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Peer")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub dropped: u64,
+    pub evicted: u64,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new(Overflow::default())
+    }
+}
+
+impl Connection {
+    fn new() -> Self {
+        let detach = CancellationToken::new();
+        Self {
+            writer: detach.child_token(),
+            detach,
+        }
+    }
+}
+
+// held by a session task for as long as it runs
+#[derive(Debug)]
+pub(crate) struct Reservation {
+    state: Arc<State>,
+    addr: SocketAddr,
+}
+
+impl Reservation {
+    pub(crate) fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.state.connections.remove(&self.addr);
+    }
+}
+
+impl Drop for PeerHandle {
+    fn drop(&mut self) {
+        // lets the writer task finish once the peer is gone from state
+        self.outbox.close();
+    }
+}
+
+impl State {
+    pub fn new(overflow: Overflow) -> Self {
+        Self {
+            peers: DashMap::new(),
+            rooms: DashMap::new(),
+            names: DashMap::new(),
+            sessions: DashMap::new(),
+            connections: DashSet::new(),
+            resume_grace: DEFAULT_RESUME_GRACE,
+            history: History::default(),
+            log: None,
+            overflow,
+            rate_limit: RateLimit::default(),
+            keepalive: Keepalive::default(),
+            admin_secret: None,
+            bans: Bans::default(),
+            accounts: Accounts::default(),
+            closing: CancellationToken::new(),
+            writers: TaskTracker::new(),
+            metrics: ChatMetrics::default(),
+            federation: Federation::default(),
+            plugins: Plugins::default(),
+            capacity: MAX_MESSAGE,
+            dropped: AtomicU64::new(0),
+            evicted: AtomicU64::new(0),
+        }
+    }
+
+    // keep the `capacity` most recent messages of every room
+    pub fn with_history(mut self, capacity: usize) -> Self {
+        self.history = History::new(capacity);
+        self
+    }
+
+    // append room messages to `log`, after restoring its entries into history
+    pub fn with_log(mut self, log: ChatLog) -> Result<Self> {
+        let count = log.restore(&self.history)?;
+        info!("Restored {} messages from the chat log", count);
+        self.log = Some(log);
+        Ok(self)
+    }
+
+    pub fn with_rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    pub fn with_keepalive(mut self, keepalive: Keepalive) -> Self {
+        self.keepalive = keepalive;
+        self
+    }
+
+    pub fn keepalive(&self) -> Keepalive {
+        self.keepalive
+    }
+
+    // zero disables session resume
+    pub fn with_resume_grace(mut self, grace: Duration) -> Self {
+        self.resume_grace = grace;
+        self
+    }
+
+    pub fn resume_grace(&self) -> Duration {
+        self.resume_grace
+    }
+
+    pub fn with_admin_secret(mut self, secret: &str) -> Self {
+        self.admin_secret = Some(blake3::hash(secret.as_bytes()));
+        self
+    }
+
+    pub fn with_bans(mut self, bans: Bans) -> Self {
+        self.bans = bans;
+        self
+    }
+
+    pub fn bans(&self) -> &Bans {
+        &self.bans
+    }
+
+    pub fn with_accounts(mut self, accounts: Accounts) -> Self {
+        self.accounts = accounts;
+        self
+    }
+
+    pub fn accounts(&self) -> &Accounts {
+        &self.accounts
+    }
+
+    pub fn with_metrics(mut self, metrics: ChatMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn metrics(&self) -> &ChatMetrics {
+        &self.metrics
+    }
+
+    pub fn with_federation(mut self, federation: Federation) -> Self {
+        self.federation = federation;
+        self
+    }
+
+    pub fn federation(&self) -> &Federation {
+        &self.federation
+    }
+
+    pub fn with_plugins(mut self, plugins: Plugins) -> Self {
+        self.plugins = plugins;
+        self
+    }
+
+    pub fn plugins(&self) -> &Plugins {
+        &self.plugins
+    }
+
+    pub fn rate_limit(&self) -> RateLimit {
+        self.rate_limit
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            dropped: self.dropped.load(Ordering::Relaxed),
+            evicted: self.evicted.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn peer_count(&self) -> usize {
+        self.peers.len()
+    }
+
+    // remove peer from state along with its room membership and name, returns username and room
+    pub fn remove(&self, addr: &SocketAddr) -> Option<(String, Option<String>)> {
+        let (_, mut peer) = self.peers.remove(addr)?;
+        self.metrics.peer_removed();
+        if let Some(room) = &peer.room {
+            self.remove_member(room, addr);
+        }
+        self.release(&peer.username, addr);
+        self.sessions.remove(&peer.token);
+        Some((std::mem::take(&mut peer.username), peer.room.take()))
+    }
+
+    // reserve `username` for `addr`, returns false if another peer holds it
+    pub fn claim(&self, addr: SocketAddr, username: &str) -> bool {
+        match self.names.entry(username.to_lowercase()) {
+            entry::Entry::Occupied(entry) => *entry.get() == addr,
+            entry::Entry::Vacant(entry) => {
+                entry.insert(addr);
+                true
+            }
+        }
+    }
+
+    // rename peer, returns the old username
+    pub fn rename(&self, addr: SocketAddr, username: &str) -> Result<String> {
+        let owner = match self.peers.get(&addr) {
+            Some(peer) => peer.account.clone(),
+            None => return Err(anyhow!("Unknown peer {}", addr)),
+        };
+        // or a banned user could log in under another name and take theirs back
+        if self.bans.is_banned_user(username) {
+            return Err(anyhow!("Username {} is banned", username));
+        }
+        if self.accounts.is_registered(username)
+            && owner.as_deref() != Some(username.to_lowercase().as_str())
+        {
+            return Err(anyhow!(
+                "Username {} is registered to someone else",
+                username
+            ));
+        }
+        if !self.claim(addr, username) {
+            return Err(anyhow!("Username {} is already taken", username));
+        }
+
+        let old = match self.peers.get_mut(&addr) {
+            Some(mut peer) => std::mem::replace(&mut peer.username, username.to_string()),
+            None => {
+                self.release(username, &addr);
+                return Err(anyhow!("Unknown peer {}", addr));
+            }
+        };
+        // a change of case only keeps the same name entry
+        if old.to_lowercase() != username.to_lowercase() {
+            self.release(&old, &addr);
+        }
+
+        Ok(old)
+    }
+
+    // address of the peer using `username`, ignoring case
+    pub fn lookup(&self, username: &str) -> Option<SocketAddr> {
+        self.names.get(&username.to_lowercase()).map(|addr| *addr)
+    }
+
+    pub fn is_closing(&self) -> bool {
+        self.closing.is_cancelled()
+    }
+
+    // resolves once `shutdown` is called
+    pub fn closing(&self) -> WaitForCancellationFuture<'_> {
+        self.closing.cancelled()
+    }
+
+    // stop accepting, tell every peer and wait up to `deadline` for their queues to flush
+    pub async fn shutdown(&self, deadline: Duration) {
+        self.closing.cancel();
+        let peers = self
+            .peers
+            .iter()
+            .map(|peer| *peer.key())
+            .collect::<Vec<_>>();
+        info!("Shutting down, disconnecting {} peers", peers.len());
+        for addr in peers {
+            self.disconnect(addr, Message::system("Server shutting down"));
+        }
+
+        self.writers.close();
+        if tokio::time::timeout(deadline, self.writers.wait())
+            .await
+            .is_err()
+        {
+            warn!(
+                "{} peers did not flush within {:?}",
+                self.writers.len(),
+                deadline
+            );
+        }
+    }
+
+    // the peer logged in as, or registered, `username`
+    pub fn set_account(&self, addr: SocketAddr, username: &str) {
+        if let Some(mut peer) = self.peers.get_mut(&addr) {
+            peer.account = Some(username.to_lowercase());
+        }
+    }
+
+    // grant peer the admin role if `secret` matches the configured one
+    pub fn authenticate(&self, addr: SocketAddr, secret: &str) -> Result<()> {
+        let expected = self
+            .admin_secret
+            .ok_or_else(|| anyhow!("Admin role is disabled on this server"))?;
+        // blake3::Hash compares in constant time
+        if blake3::hash(secret.as_bytes()) != expected {
+            return Err(anyhow!("Wrong admin secret"));
+        }
+        let mut peer = self
+            .peers
+            .get_mut(&addr)
+            .ok_or_else(|| anyhow!("Unknown peer {}", addr))?;
+        peer.admin = true;
+        Ok(())
+    }
+
+    pub fn is_admin(&self, addr: &SocketAddr) -> bool {
+        self.peers.get(addr).is_some_and(|peer| peer.admin)
+    }
+
+    // stop peer from talking for `duration`, returns false for an unknown peer
+    pub fn mute(&self, addr: SocketAddr, duration: Duration) -> Result<bool> {
+        let until = Instant::now()
+            .checked_add(duration)
+            .ok_or_else(|| anyhow!("Mute duration is too long"))?;
+        match self.peers.get_mut(&addr) {
+            Some(mut peer) => {
+                peer.muted_until = Some(until);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub fn is_muted(&self, addr: &SocketAddr) -> bool {
+        self.peers
+            .get(addr)
+            .and_then(|peer| peer.muted_until)
+            .is_some_and(|until| Instant::now() < until)
+    }
+
+    // connected peers coming from `ip`
+    pub fn peers_from(&self, ip: IpAddr) -> Vec<SocketAddr> {
+        self.peers
+            .iter()
+            .map(|peer| *peer.key())
+            .filter(|addr| addr.ip() == ip)
+            .collect()
+    }
+
+    // move peer into `room`, returns the room it left if any
+    pub fn join(&self, addr: SocketAddr, room: &str) -> Option<String> {
+        let prev = {
+            let mut peer = self.peers.get_mut(&addr)?;
+            if peer.room.as_deref() == Some(room) {
+                return None;
+            }
+            peer.room.replace(room.to_string())
+        };
+
+        if let Some(prev) = &prev {
+            self.remove_member(prev, &addr);
+        }
+        match self.rooms.entry(room.to_string()) {
+            entry::Entry::Occupied(mut members) => {
+                members.get_mut().insert(addr);
+            }
+            entry::Entry::Vacant(vacant) => {
+                vacant.insert(BTreeSet::from([addr]));
+                self.metrics.room_opened();
+            }
+        }
+
+        prev
+    }
+
+    // take peer out of its room, returns the room it left if any
+    pub fn leave(&self, addr: SocketAddr) -> Option<String> {
+        let room = self.peers.get_mut(&addr)?.room.take()?;
+        self.remove_member(&room, &addr);
+        Some(room)
+    }
+
+    pub fn room_of(&self, addr: &SocketAddr) -> Option<String> {
+        self.peers.get(addr)?.room.clone()
+    }
+
+    // rooms with their member count, linked servers included, sorted by name
+    pub fn rooms(&self) -> Vec<(String, usize)> {
+        let mut rooms = self.federation.rooms();
+        for room in self.rooms.iter() {
+            *rooms.entry(room.key().clone()).or_insert(0) += room.value().len();
+        }
+        let mut rooms = rooms.into_iter().collect::<Vec<_>>();
+        rooms.sort();
+        rooms
+    }
+
+    // usernames in `room`, sorted, then those on linked servers as user@server
+    pub fn who(&self, room: &str) -> Vec<String> {
+        let members = self
+            .rooms
+            .get(room)
+            .map(|members| members.clone())
+            .unwrap_or_default();
+
+        let mut names = members
+            .iter()
+            .filter_map(|addr| self.peers.get(addr).map(|peer| peer.username.clone()))
+            .collect::<Vec<_>>();
+        names.sort();
+        names.extend(self.federation.who(room));
+        names
+    }
+
+    // username and room of every local peer in a room
+    pub(crate) fn members(&self) -> Vec<(String, String)> {
+        self.peers
+            .iter()
+            .filter_map(|peer| Some((peer.username.clone(), peer.room.clone()?)))
+            .collect()
+    }
+
+    // queue message for every member of `room` except the sender, never waits on a slow peer;
+    // messages of the room itself are kept in history and the chat log
+    pub fn broadcast(&self, room: &str, addr: SocketAddr, msg: Message) {
+        self.spread(room, Some(addr), msg);
+    }
+
+    // like `broadcast`, without a sender to skip
+    pub fn post(&self, room: &str, msg: Message) {
+        self.spread(room, None, msg);
+    }
+
+    fn spread(&self, room: &str, except: Option<SocketAddr>, msg: Message) {
+        let entry = Arc::new(Entry::new(msg));
+        if entry.message.room() == Some(room) {
+            self.record(&entry);
+            self.federation.publish(&entry);
+        }
+        self.fan_out(room, except, entry);
+    }
+
+    // a room message from a linked server, for the local members of its room
+    pub(crate) fn deliver(&self, entry: Arc<Entry>) {
+        let Some(room) = entry.message.room().map(str::to_string) else {
+            return;
+        };
+        self.record(&entry);
+        self.fan_out(&room, None, entry);
+    }
+
+    fn record(&self, entry: &Arc<Entry>) {
+        if let Some(log) = &self.log {
+            log.append(entry);
+        }
+        self.history.record(entry.clone());
+    }
+
+    fn fan_out(&self, room: &str, except: Option<SocketAddr>, entry: Arc<Entry>) {
+        let started = Instant::now();
+        // collect the outboxes first, so no map lock is held while pushing
+        let members = match self.rooms.get(room) {
+            Some(members) => members.clone(),
+            None => return,
+        };
+        let outboxes = members
+            .into_iter()
+            .filter(|member| Some(*member) != except)
+            .filter_map(|member| {
+                self.peers
+                    .get(&member)
+                    .map(|peer| (member, peer.outbox.clone()))
+            })
+            .collect::<Vec<_>>();
+
+        for (member, outbox) in outboxes {
+            self.push(member, &outbox, entry.clone());
+        }
+        self.metrics.broadcast(started.elapsed());
+    }
+
+    // queue message for a single peer
+    pub fn send(&self, addr: SocketAddr, msg: Message) {
+        self.send_entry(addr, Arc::new(Entry::new(msg)));
+    }
+
+    // queue message for every connected peer, whatever their room
+    pub fn announce(&self, msg: Message) {
+        let entry = Arc::new(Entry::new(msg));
+        let outboxes = self
+            .peers
+            .iter()
+            .map(|peer| (*peer.key(), peer.outbox.clone()))
+            .collect::<Vec<_>>();
+        for (addr, outbox) in outboxes {
+            self.push(addr, &outbox, entry.clone());
+        }
+    }
+
+    // send peer a last message and close its connection, false if it is already gone
+    pub fn disconnect(&self, addr: SocketAddr, msg: Message) -> bool {
+        let outbox = match self.peers.get(&addr) {
+            Some(peer) => peer.outbox.clone(),
+            None => return false,
+        };
+        // like an eviction, the peer's own task removes it from state
+        outbox.close_with(Arc::new(Entry::new(msg)))
+    }
+
+    // queue up to `n` latest messages of `room` for a single peer, keeping their timestamps
+    pub fn replay(&self, addr: SocketAddr, room: &str, n: usize) {
+        let entries = self.history.recent(room, n);
+        if entries.is_empty() {
+            return;
+        }
+
+        let header = format!("Last {} messages in {}:", entries.len(), room);
+        self.send(addr, Message::system(header));
+        for entry in entries {
+            self.send_entry(addr, entry);
+        }
+    }
+
+    fn send_entry(&self, addr: SocketAddr, entry: Arc<Entry>) {
+        let outbox = match self.peers.get(&addr) {
+            Some(peer) => peer.outbox.clone(),
+            None => return,
+        };
+        self.push(addr, &outbox, entry);
+    }
+
+    fn push(&self, addr: SocketAddr, outbox: &Outbox, entry: Arc<Entry>) {
+        match outbox.push(entry, self.overflow) {
+            Push::Queued(depth) => self.metrics.queued(depth),
+            Push::Closed => {}
+            Push::Dropped => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                self.metrics.dropped(1);
+            }
+            Push::Evicted(lost) => {
+                // the peer's own task removes it from state once it sees the outbox closed
+                warn!("Evicting slow peer {}", addr);
+                self.dropped.fetch_add(lost, Ordering::Relaxed);
+                self.metrics.dropped(lost);
+                self.evicted.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    // `None` while another session uses `addr`, the address is free again once the reservation
+    // is dropped
+    pub(crate) fn reserve(self: &Arc<Self>, addr: SocketAddr) -> Option<Reservation> {
+        self.connections.insert(addr).then(|| Reservation {
+            state: self.clone(),
+            addr,
+        })
+    }
+
+    // add peer to state and return peer
+    pub async fn add(
+        &self,
+        addr: SocketAddr,
+        username: String,
+        protocol: Protocol,
+        sink: LineSink,
+        stream: LineStream,
+    ) -> Peer {
+        let (outbox, token, conn) = self.insert(addr, username.clone());
+        self.attach(addr, outbox.clone(), conn.writer, protocol, sink);
+
+        Peer {
+            username,
+            stream,
+            token,
+            closed: outbox.closed(),
+            detached: conn.detach,
+        }
+    }
+
+    pub fn can_resume(&self, token: &str) -> bool {
+        self.sessions.contains_key(token)
+    }
+
+    // resume token of the session at `addr`
+    pub(crate) fn token_of(&self, addr: &SocketAddr) -> Option<String> {
+        self.peers.get(addr).map(|peer| peer.token.clone())
+    }
+
+    // where the session of `token` is connected now, it moves on every resume
+    pub(crate) fn session_addr(&self, token: &str) -> Option<SocketAddr> {
+        self.sessions.get(token).map(|addr| *addr)
+    }
+
+    // move the session of `token` over to the connection at `addr`, messages queued meanwhile
+    // included; its previous connection is detached without telling the room
+    pub fn resume(
+        &self,
+        token: &str,
+        addr: SocketAddr,
+        protocol: Protocol,
+        sink: LineSink,
+        stream: LineStream,
+    ) -> Result<Peer> {
+        let old = self
+            .sessions
+            .get(token)
+            .map(|old| *old)
+            .ok_or_else(|| anyhow!("Unknown or expired resume token"))?;
+        let (_, mut handle) = self
+            .peers
+            .remove(&old)
+            .ok_or_else(|| anyhow!("Unknown or expired resume token"))?;
+        handle.conn.detach.cancel();
+        handle.conn = Connection::new();
+
+        if let Some(room) = &handle.room {
+            if let Some(mut members) = self.rooms.get_mut(room) {
+                members.remove(&old);
+                members.insert(addr);
+            }
+        }
+        if let Some(mut owner) = self.names.get_mut(&handle.username.to_lowercase()) {
+            if *owner == old {
+                *owner = addr;
+            }
+        }
+        self.sessions.insert(token.to_string(), addr);
+
+        let username = handle.username.clone();
+        let outbox = handle.outbox.clone();
+        let conn = handle.conn.clone();
+        self.peers.insert(addr, handle);
+        self.attach(addr, outbox.clone(), conn.writer, protocol, sink);
+
+        Ok(Peer {
+            username,
+            stream,
+            token: token.to_string(),
+            closed: outbox.closed(),
+            detached: conn.detach,
+        })
+    }
+
+    // the connection of `addr` is gone, keep its session and queue for a resume
+    pub fn park(&self, addr: &SocketAddr) {
+        if let Some(peer) = self.peers.get(addr) {
+            peer.conn.writer.cancel();
+        }
+    }
+
+    // write the peer's queue to `sink` until the outbox closes or the connection is detached
+    fn attach(
+        &self,
+        addr: SocketAddr,
+        outbox: Arc<Outbox>,
+        detached: CancellationToken,
+        protocol: Protocol,
+        mut sink: LineSink,
+    ) {
+        let metrics = self.metrics.clone();
+        self.writers.spawn(async move {
+            loop {
+                // checked first, so a replaced writer can't take the new one's entries
+                let entry = tokio::select! {
+                    biased;
+                    _ = detached.cancelled() => return,
+                    entry = outbox.recv() => entry,
+                };
+                let Some(entry) = entry else {
+                    break;
+                };
+                if let Err(e) = sink.send(protocol.encode(&entry)).await {
+                    warn!("Failed to send message to {}: {}", addr, e);
+                    return;
+                }
+                metrics.sent();
+            }
+            // flushed, let the client see a clean close
+            let _ = sink.close().await;
+        });
+    }
+
+    #[cfg(test)]
+    pub(crate) fn register(&self, addr: SocketAddr, username: String) -> Arc<Outbox> {
+        self.insert(addr, username).0
+    }
+
+    fn insert(&self, addr: SocketAddr, username: String) -> (Arc<Outbox>, String, Connection) {
+        let outbox = Arc::new(Outbox::new(self.capacity));
+        let token = nanoid::nanoid!();
+        let conn = Connection::new();
+        self.sessions.insert(token.clone(), addr);
+        let replaced = self.peers.insert(
+            addr,
+            PeerHandle {
+                username,
+                room: None,
+                outbox: outbox.clone(),
+                admin: false,
+                muted_until: None,
+                account: None,
+                token: token.clone(),
+                conn: conn.clone(),
+            },
+        );
+        if replaced.is_none() {
+            self.metrics.peer_added();
+        }
+        // a peer that logged in while the server started closing
+        if self.is_closing() {
+            outbox.close_with(Arc::new(Entry::new(Message::system(
+                "Server shutting down",
+            ))));
+        }
+        (outbox, token, conn)
+    }
+
+    fn release(&self, username: &str, addr: &SocketAddr) {
+        self.names
+            .remove_if(&username.to_lowercase(), |_, owner| owner == addr);
+    }
+
+    fn remove_member(&self, room: &str, addr: &SocketAddr) {
+        if let Some(mut members) = self.rooms.get_mut(room) {
+            members.remove(addr);
+        }
+        // clean up the room once the last member is gone
+        if self
+            .rooms
+            .remove_if(room, |_, members| members.is_empty())
+            .is_some()
+        {
+            self.metrics.room_closed();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::{self, Executor};
+    use std::{cell::RefCell, rc::Rc, time::Duration};
+
+    fn peer(state: &State, port: u16, name: &str) -> (SocketAddr, Arc<Outbox>) {
+        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+        assert!(state.claim(addr, name));
+        let outbox = state.register(addr, name.to_string());
+        (addr, outbox)
+    }
+
+    #[tokio::test]
+    async fn broadcast_should_skip_sender() {
+        let state = State::default();
+        let (alice, alice_rx) = peer(&state, 1001, "alice");
+        let (bob, bob_rx) = peer(&state, 1002, "bob");
+        state.join(alice, DEFAULT_ROOM);
+        state.join(bob, DEFAULT_ROOM);
+
+        let msg = Message::joined(DEFAULT_ROOM, "alice");
+        state.broadcast(DEFAULT_ROOM, alice, msg.clone());
+
+        assert_eq!(bob_rx.recv().await.unwrap().message, msg);
+        assert_eq!(alice_rx.try_recv(), None);
+    }
+
+    #[tokio::test]
+    async fn broadcast_should_stay_in_room() {
+        let state = State::default();
+        let (alice, _alice_rx) = peer(&state, 1001, "alice");
+        let (bob, bob_rx) = peer(&state, 1002, "bob");
+        let (carol, carol_rx) = peer(&state, 1003, "carol");
+        state.join(alice, "rust");
+        state.join(bob, "rust");
+        state.join(carol, DEFAULT_ROOM);
+
+        let msg = Message::chat("rust", "alice", "hi");
+        state.broadcast("rust", alice, msg.clone());
+
+        assert_eq!(bob_rx.recv().await.unwrap().message, msg);
+        assert_eq!(carol_rx.try_recv(), None);
+    }
+
+    #[test]
+    fn rooms_should_track_membership() {
+        let state = State::default();
+        let (alice, _alice_rx) = peer(&state, 1001, "alice");
+        let (bob, _bob_rx) = peer(&state, 1002, "bob");
+
+        assert_eq!(state.join(alice, DEFAULT_ROOM), None);
+        assert_eq!(state.join(bob, DEFAULT_ROOM), None);
+        assert_eq!(state.join(alice, DEFAULT_ROOM), None);
+        assert_eq!(state.join(alice, "rust"), Some(DEFAULT_ROOM.to_string()));
+
+        assert_eq!(
+            state.rooms(),
+            vec![(DEFAULT_ROOM.to_string(), 1), ("rust".to_string(), 1)]
+        );
+        assert_eq!(state.who("rust"), vec!["alice"]);
+        assert_eq!(state.room_of(&bob).as_deref(), Some(DEFAULT_ROOM));
+
+        // empty rooms are cleaned up
+        assert_eq!(state.leave(alice), Some("rust".to_string()));
+        assert_eq!(state.rooms(), vec![(DEFAULT_ROOM.to_string(), 1)]);
+        assert_eq!(state.leave(alice), None);
+
+        assert_eq!(
+            state.remove(&bob),
+            Some(("bob".to_string(), Some(DEFAULT_ROOM.to_string())))
+        );
+        assert!(state.rooms().is_empty());
+        assert!(state.who(DEFAULT_ROOM).is_empty());
+    }
+
+    #[test]
+    fn broadcast_should_record_room_history() {
+        let state = State::default().with_history(2);
+        let (alice, _alice_rx) = peer(&state, 1001, "alice");
+        state.join(alice, "rust");
+
+        for i in 0..3 {
+            let msg = Message::chat("rust", "alice", i.to_string());
+            state.broadcast("rust", alice, msg);
+        }
+        // a message about another room is only passed along
+        state.broadcast("rust", alice, Message::joined("go", "bob"));
+
+        let recent = state.history().recent("rust", 10);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[1].message.to_string(), "[rust] alice: 2");
+        assert!(state.history().recent("go", 10).is_empty());
+    }
+
+    #[test]
+    fn names_should_be_unique() {
+        let state = State::default();
+        let (alice, _alice_rx) = peer(&state, 1001, "alice");
+        let (bob, _bob_rx) = peer(&state, 1002, "bob");
+
+        assert!(!state.claim(bob, "Alice"));
+        assert!(state.claim(alice, "alice"));
+        assert_eq!(state.lookup("ALICE"), Some(alice));
+
+        assert!(state.rename(bob, "alice").is_err());
+        assert_eq!(state.rename(alice, "Alice").unwrap(), "alice");
+        assert_eq!(state.rename(alice, "alicia").unwrap(), "Alice");
+        assert_eq!(state.lookup("alice"), None);
+        assert_eq!(state.lookup("alicia"), Some(alice));
+
+        // the old name is free again, and so is the new one once the peer is gone
+        assert!(state.claim(bob, "alice"));
+        state.remove(&alice);
+        assert_eq!(state.lookup("alicia"), None);
+    }
+
+    // three peers broadcast concurrently, sleeping on the virtual clock in between so
+    // the seeded scheduler decides the interleaving; returns what every peer received
+    fn concurrent_broadcast(seed: u64) -> Vec<(u16, String)> {
+        Executor::deterministic(seed).block_on(async {
+            let state = Rc::new(State::default());
+            let log = Rc::new(RefCell::new(Vec::new()));
+            let mut readers = Vec::new();
+
+            for port in 1..=3 {
+                let addr = SocketAddr::from(([127, 0, 0, 1], port));
+                let outbox = state.register(addr, port.to_string());
+                state.join(addr, DEFAULT_ROOM);
+
+                let log = log.clone();
+                readers.push(executor::spawn(async move {
+                    while let Some(entry) = outbox.recv().await {
+                        log.borrow_mut().push((port, entry.message.to_string()));
+                    }
+                }));
+            }
+
+            let writers = (1..=3)
+                .map(|port| {
+                    let state = state.clone();
+                    executor::spawn(async move {
+                        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+                        for i in 0..3 {
+                            let msg = Message::chat(DEFAULT_ROOM, port.to_string(), i.to_string());
+                            state.broadcast(DEFAULT_ROOM, addr, msg);
+                            executor::sleep(Duration::from_millis(1)).await;
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            for writer in writers {
+                writer.await;
+            }
+            // dropping the handles closes every outbox and ends the reader loops
+            state.peers.clear();
+            for reader in readers {
+                reader.await;
+            }
+
+            log.take()
+        })
+    }
+
+    #[test]
+    fn broadcast_should_be_reproducible_with_seed() {
+        let trace = concurrent_broadcast(42);
+        assert_eq!(trace, concurrent_broadcast(42));
+
+        // every peer gets the 6 messages of the other two, each sender in order
+        for port in 1..=3 {
+            let received = trace
+                .iter()
+                .filter(|(p, _)| *p == port)
+                .map(|(_, msg)| msg.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(received.len(), 6);
+
+            for sender in (1..=3).filter(|sender| *sender != port) {
+                let from_sender = received
+                    .iter()
+                    .filter(|msg| msg.starts_with(&format!("[{}] {}:", DEFAULT_ROOM, sender)))
+                    .copied()
+                    .collect::<Vec<_>>();
+                let expected = (0..3)
+                    .map(|i| format!("[{}] {}: {}", DEFAULT_ROOM, sender, i))
+                    .collect::<Vec<_>>();
+                assert_eq!(from_sender, expected);
+            }
+        }
+
+        let traces = (0..8)
+            .map(concurrent_broadcast)
+            .collect::<std::collections::HashSet<_>>();
+        assert!(traces.len() > 1);
+    }
+
+    #[test]
+    fn full_queue_should_follow_overflow_policy() {
+        for (overflow, expected) in [
+            (Overflow::DropOldest, vec!["1", "2"]),
+            (Overflow::DropMessage, vec!["0", "1"]),
+        ] {
+            let mut state = State::new(overflow);
+            state.capacity = 2;
+            let (alice, _alice_rx) = peer(&state, 1001, "alice");
+            let (bob, bob_rx) = peer(&state, 1002, "bob");
+            state.join(alice, DEFAULT_ROOM);
+            state.join(bob, DEFAULT_ROOM);
+
+            for i in 0..3 {
+                let msg = Message::chat(DEFAULT_ROOM, "alice", i.to_string());
+                state.broadcast(DEFAULT_ROOM, alice, msg);
+            }
+
+            let received = std::iter::from_fn(|| bob_rx.try_recv())
+                .map(|entry| match &entry.message {
+                    Message::Chat { content, .. } => content.clone(),
+                    msg => panic!("unexpected {:?}", msg),
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(received, expected);
+            assert_eq!(
+                state.stats(),
+                Stats {
+                    dropped: 1,
+                    evicted: 0
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn slow_peer_should_be_evicted() {
+        let mut state = State::new(Overflow::Disconnect);
+        state.capacity = 1;
+        let (alice, _alice_rx) = peer(&state, 1001, "alice");
+        let (bob, bob_rx) = peer(&state, 1002, "bob");
+        state.join(alice, DEFAULT_ROOM);
+        state.join(bob, DEFAULT_ROOM);
+
+        for i in 0..3 {
+            let msg = Message::chat(DEFAULT_ROOM, "alice", i.to_string());
+            state.broadcast(DEFAULT_ROOM, alice, msg);
+        }
+
+        assert!(bob_rx.closed().is_cancelled());
+        assert!(matches!(
+            &bob_rx.try_recv().unwrap().message,
+            Message::Error { .. }
+        ));
+        assert_eq!(bob_rx.try_recv(), None);
+        assert_eq!(
+            state.stats(),
+            Stats {
+                dropped: 2,
+                evicted: 1
+            }
+        );
+    }
+
+    // thousands of peers share a room, a tenth of them never read; broadcasting must
+    // not stall on them and every message is either delivered or counted as dropped
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn broadcast_should_not_stall_on_slow_peers() {
+        const PEERS: u16 = 2000;
+        const SENDERS: u16 = 10;
+        const MESSAGES: usize = 50;
+
+        let state = Arc::new(State::new(Overflow::DropOldest));
+        let mut readers = Vec::new();
+        let mut slow = Vec::new();
+        for port in 1..=PEERS {
+            let (addr, outbox) = peer(&state, port, &format!("user{}", port));
+            state.join(addr, DEFAULT_ROOM);
+            if port % 10 == 0 {
+                slow.push(outbox);
+                continue;
+            }
+            readers.push(tokio::spawn(async move {
+                let mut received = 0;
+                while outbox.recv().await.is_some() {
+                    received += 1;
+                }
+                (port, received, outbox.dropped())
+            }));
+        }
+
+        let start = std::time::Instant::now();
+        let senders = (1..=SENDERS)
+            .map(|port| {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+                    for i in 0..MESSAGES {
+                        let msg = Message::chat(DEFAULT_ROOM, port.to_string(), i.to_string());
+                        state.broadcast(DEFAULT_ROOM, addr, msg);
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for sender in senders {
+            sender.await.unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        // senders receive everything but their own messages
+        let expected = |port: u16| {
+            let own = if port <= SENDERS { MESSAGES } else { 0 };
+            (SENDERS as usize * MESSAGES - own) as u64
+        };
+        for outbox in &slow {
+            assert_eq!(outbox.len(), MAX_MESSAGE);
+        }
+
+        state.peers.clear();
+        let mut dropped = slow.iter().map(|outbox| outbox.dropped()).sum::<u64>();
+        for reader in readers {
+            let (port, received, lost) = reader.await.unwrap();
+            assert_eq!(received + lost, expected(port));
+            dropped += lost;
+        }
+        assert_eq!(state.stats().dropped, dropped);
+        assert_eq!(state.stats().evicted, 0);
+    }
+}
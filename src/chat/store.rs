@@ -0,0 +1,92 @@
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+// a JSON file holding one value, rewritten whole on every change
+#[derive(Debug, Clone)]
+pub(crate) struct JsonFile {
+    path: PathBuf,
+    // what the file holds, for error messages
+    what: &'static str,
+    // readable by the owner only, for files holding secrets
+    private: bool,
+}
+
+impl JsonFile {
+    pub(crate) fn new(path: impl Into<PathBuf>, what: &'static str) -> Self {
+        Self {
+            path: path.into(),
+            what,
+            private: false,
+        }
+    }
+
+    pub(crate) fn private(mut self) -> Self {
+        self.private = true;
+        self
+    }
+
+    // a missing file reads as the default value
+    pub(crate) fn load<T: DeserializeOwned + Default>(&self) -> Result<T> {
+        match fs::read_to_string(&self.path) {
+            Ok(content) => serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse {} {}", self.what, self.path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+            Err(e) => Err(e)
+                .with_context(|| format!("Failed to read {} {}", self.what, self.path.display())),
+        }
+    }
+
+    // write then rename, so a crash never leaves a half written file
+    pub(crate) fn save<T: Serialize + ?Sized>(&self, value: &T) -> Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        self.write(&tmp, &serde_json::to_vec_pretty(value)?)
+            .with_context(|| format!("Failed to write {} {}", self.what, tmp.display()))?;
+        fs::rename(&tmp, &self.path)
+            .with_context(|| format!("Failed to write {} {}", self.what, self.path.display()))?;
+        Ok(())
+    }
+
+    fn write(&self, path: &Path, content: &[u8]) -> std::io::Result<()> {
+        let mut file = File::create(path)?;
+        #[cfg(unix)]
+        if self.private {
+            use std::os::unix::fs::PermissionsExt;
+            // before any content is written, so the secrets are never readable by others
+            file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        }
+        file.write_all(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_file_should_round_trip() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("store-{}.json", nanoid::nanoid!()));
+        let file = JsonFile::new(&path, "test file").private();
+        assert_eq!(file.load::<Vec<String>>()?, Vec::<String>::new());
+
+        file.save(&["a", "b"])?;
+        assert_eq!(file.load::<Vec<String>>()?, vec!["a", "b"]);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path)?.permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        fs::write(&path, "not json")?;
+        let err = file.load::<Vec<String>>().unwrap_err();
+        assert!(err.to_string().starts_with("Failed to parse test file"));
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+}
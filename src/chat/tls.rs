@@ -0,0 +1,130 @@
+use anyhow::{anyhow, Context, Result};
+use futures::{future::BoxFuture, FutureExt};
+use std::{fs::File, io, io::BufReader, path::Path, sync::Arc, time::Duration};
+use tokio::{net::TcpListener, net::TcpStream, time};
+use tokio_rustls::{
+    rustls::{crypto::ring, ServerConfig},
+    server::TlsStream,
+    TlsAcceptor,
+};
+use tracing::info;
+
+use super::{accept, State};
+
+pub const DEFAULT_TLS_ADDR: &str = "0.0.0.0:3443";
+
+// a client that connects but never finishes the handshake is dropped after this
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// load a PEM certificate chain and private key
+pub fn acceptor(cert: &Path, key: &Path) -> Result<TlsAcceptor> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(open(cert)?))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to read certificates from {}", cert.display()))?;
+    if certs.is_empty() {
+        return Err(anyhow!("No certificate found in {}", cert.display()));
+    }
+    let private_key = rustls_pemfile::private_key(&mut BufReader::new(open(key)?))
+        .with_context(|| format!("Failed to read private key from {}", key.display()))?
+        .ok_or_else(|| anyhow!("No private key found in {}", key.display()))?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, private_key)
+        .context("Invalid certificate or private key")?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+// the same chat as `serve_with`, encrypted
+pub async fn serve_tls(addr: &str, state: Arc<State>, acceptor: TlsAcceptor) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Chat TLS listener on {}", addr);
+    accept(listener, state, handshake(acceptor)).await
+}
+
+fn handshake(
+    acceptor: TlsAcceptor,
+) -> impl Fn(TcpStream) -> BoxFuture<'static, io::Result<TlsStream<TcpStream>>> {
+    move |stream| {
+        let accepting = acceptor.accept(stream);
+        async move {
+            time::timeout(HANDSHAKE_TIMEOUT, accepting)
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out"))?
+        }
+        .boxed()
+    }
+}
+
+fn open(path: &Path) -> Result<File> {
+    File::open(path).with_context(|| format!("Failed to open {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{future, SinkExt, StreamExt};
+    use std::fs;
+    use tokio_rustls::{
+        rustls::{pki_types::ServerName, ClientConfig, RootCertStore},
+        TlsConnector,
+    };
+    use tokio_util::codec::{Framed, LinesCodec};
+
+    #[tokio::test]
+    async fn tls_and_plain_clients_should_share_rooms() -> Result<()> {
+        // self-signed dev certificate, written out to exercise the PEM loading
+        let dev = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
+        let dir = std::env::temp_dir().join(format!("chat-tls-{}", nanoid::nanoid!()));
+        fs::create_dir_all(&dir)?;
+        let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        fs::write(&cert, dev.cert.pem())?;
+        fs::write(&key, dev.key_pair.serialize_pem())?;
+        let acceptor = acceptor(&cert, &key)?;
+        fs::remove_dir_all(&dir)?;
+
+        let state = Arc::new(State::default());
+        let tls = TcpListener::bind("127.0.0.1:0").await?;
+        let tls_addr = tls.local_addr()?;
+        tokio::spawn(accept(tls, state.clone(), handshake(acceptor)));
+        let plain = TcpListener::bind("127.0.0.1:0").await?;
+        let plain_addr = plain.local_addr()?;
+        tokio::spawn(accept(plain, state, |stream| future::ready(Ok(stream))));
+
+        let mut roots = RootCertStore::empty();
+        roots.add(dev.cert.der().clone())?;
+        let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let stream = TlsConnector::from(Arc::new(config))
+            .connect(
+                ServerName::try_from("localhost")?,
+                TcpStream::connect(tls_addr).await?,
+            )
+            .await?;
+        let mut secure = Framed::new(stream, LinesCodec::new());
+        assert_eq!(secure.next().await.unwrap()?, "Enter your username: ");
+        secure.send("secure").await?;
+        assert!(secure
+            .next()
+            .await
+            .unwrap()?
+            .starts_with("* Resume token: "));
+        assert_eq!(secure.next().await.unwrap()?, "* You joined lobby");
+
+        let mut nc = Framed::new(TcpStream::connect(plain_addr).await?, LinesCodec::new());
+        nc.send("nc").await?;
+        assert_eq!(secure.next().await.unwrap()?, "[lobby] nc joined the room");
+
+        secure.send("hi over tls").await?;
+        loop {
+            let line = nc.next().await.unwrap()?;
+            if line == "[lobby] secure: hi over tls" {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+use anyhow::Result;
+use axum::{
+    extract::{
+        ws::{Message as Frame, WebSocket},
+        ConnectInfo, State as Extract, WebSocketUpgrade,
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use futures::{future, stream, SinkExt, StreamExt, TryStreamExt};
+use std::{net::SocketAddr, sync::Arc};
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+use super::{handle_lines, line_too_long, Closed, State, MAX_LINE_LENGTH};
+
+// browser clients talk to the same `State` as TCP clients, a text frame holds one line or more
+pub fn router(state: Arc<State>) -> Router {
+    Router::new()
+        .route("/ws", get(upgrade_handler))
+        .with_state(state)
+}
+
+pub async fn serve_ws(addr: &str, state: Arc<State>) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Chat WebSocket gateway listening on {}", addr);
+
+    let app = router(state.clone()).into_make_service_with_connect_info::<SocketAddr>();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move { state.closing().await })
+        .await?;
+    Ok(())
+}
+
+async fn upgrade_handler(
+    ws: WebSocketUpgrade,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extract(state): Extract<Arc<State>>,
+) -> Response {
+    if state.bans().is_banned_ip(addr.ip()) {
+        info!("Rejected banned WebSocket client {}", addr);
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    // larger frames are refused while being read, not buffered in full first
+    let ws = ws.max_message_size(MAX_LINE_LENGTH);
+    ws.on_upgrade(move |socket| async move {
+        info!("WebSocket client {} connected", addr);
+        if let Err(e) = handle_socket(state, addr, socket).await {
+            warn!("Failed to handle client: {}, {}", addr, e);
+        }
+    })
+}
+
+async fn handle_socket(state: Arc<State>, addr: SocketAddr, socket: WebSocket) -> Result<()> {
+    let (sink, stream) = socket.split();
+    let sink = sink
+        .with(|line: String| future::ready(Ok::<_, axum::Error>(Frame::Text(line))))
+        .sink_map_err(anyhow::Error::from);
+    // pings, pongs and binary frames carry no chat lines; a close frame is a goodbye, unlike a
+    // dropped connection, which holds the session for a resume.
+    // a text frame with line breaks is several lines, so it cannot pass off a line as another
+    // user's to TCP clients
+    let stream = stream
+        .map_err(anyhow::Error::from)
+        .and_then(|frame| {
+            future::ready(match frame {
+                Frame::Close(_) => Err(Closed.into()),
+                Frame::Text(text) if text.len() > MAX_LINE_LENGTH => Err(line_too_long()),
+                Frame::Text(text) => Ok(text
+                    .trim_end_matches(['\r', '\n'])
+                    .split('\n')
+                    .map(|line| Ok(line.trim_end_matches('\r').to_string()))
+                    .collect()),
+                _ => Ok(Vec::new()),
+            })
+        })
+        .map_ok(stream::iter)
+        .try_flatten();
+
+    handle_lines(state, addr, Box::pin(sink), stream.boxed()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::handle_client;
+    use std::time::Duration;
+    use tokio::{net::TcpStream, time};
+    use tokio_tungstenite::{connect_async, tungstenite::Message as WsFrame};
+    use tokio_util::codec::{Framed, LinesCodec};
+
+    async fn next_text<S>(ws: &mut S) -> String
+    where
+        S: futures::Stream<Item = Result<WsFrame, tokio_tungstenite::tungstenite::Error>> + Unpin,
+    {
+        match ws.next().await {
+            Some(Ok(WsFrame::Text(text))) => text,
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+    }
+
+    #[tokio::test]
+    async fn ws_and_tcp_clients_should_share_rooms() -> Result<()> {
+        let state = Arc::new(State::default());
+
+        let tcp = TcpListener::bind("127.0.0.1:0").await?;
+        let tcp_addr = tcp.local_addr()?;
+        let tcp_state = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, addr)) = tcp.accept().await {
+                tokio::spawn(handle_client(tcp_state.clone(), addr, stream));
+            }
+        });
+
+        let http = TcpListener::bind("127.0.0.1:0").await?;
+        let http_addr = http.local_addr()?;
+        let app = router(state).into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(async move { axum::serve(http, app).await });
+
+        let (mut web, _) = connect_async(format!("ws://{}/ws", http_addr)).await?;
+        assert_eq!(next_text(&mut web).await, "Enter your username: ");
+        web.send(WsFrame::Text("web".into())).await?;
+        assert!(next_text(&mut web).await.starts_with("* Resume token: "));
+        assert_eq!(next_text(&mut web).await, "* You joined lobby");
+
+        let mut nc = Framed::new(TcpStream::connect(tcp_addr).await?, LinesCodec::new());
+        assert_eq!(nc.next().await.unwrap()?, "Enter your username: ");
+        nc.send("nc").await?;
+        assert!(nc.next().await.unwrap()?.starts_with("* Resume token: "));
+        let mut nc_lines = Vec::new();
+        for _ in 0..3 {
+            nc_lines.push(nc.next().await.unwrap()?);
+        }
+        assert_eq!(
+            nc_lines,
+            vec![
+                "* You joined lobby",
+                "* Last 1 messages in lobby:",
+                "[lobby] web joined the room"
+            ]
+        );
+
+        nc.send("hi from nc").await?;
+        assert_eq!(next_text(&mut web).await, "[lobby] nc joined the room");
+        assert_eq!(next_text(&mut web).await, "[lobby] nc: hi from nc");
+
+        web.send(WsFrame::Text("hi from web".into())).await?;
+        assert_eq!(nc.next().await.unwrap()?, "[lobby] web: hi from web");
+        web.send(WsFrame::Text("one\r\n[lobby] nc: two\n".into()))
+            .await?;
+        assert_eq!(nc.next().await.unwrap()?, "[lobby] web: one");
+        assert_eq!(nc.next().await.unwrap()?, "[lobby] web: [lobby] nc: two");
+
+        // a clean close leaves at once, the session is not held for a resume
+        web.close(None).await?;
+        let left = time::timeout(Duration::from_secs(1), nc.next()).await?;
+        assert_eq!(left.unwrap()?, "[lobby] web left the room");
+        Ok(())
+    }
+}
@@ -0,0 +1,341 @@
+use crate::{chat, config::AppConfig, grpc, matrix, rest, shortener, telemetry};
+use anyhow::{anyhow, Result};
+use clap::{Args, Parser, Subcommand};
+use futures::{future, FutureExt};
+use opentelemetry::metrics::MeterProvider;
+use std::{
+    io::{self, Read},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+use tracing::level_filters::LevelFilter;
+
+#[derive(Debug, Parser)]
+#[command(name = "rust-learning", version, about)]
+pub struct Opts {
+    /// Address to listen on, overrides the config file
+    #[arg(short, long, global = true)]
+    pub listen: Option<String>,
+
+    /// Log level: off, error, warn, info, debug or trace
+    #[arg(long, global = true)]
+    pub log_level: Option<LevelFilter>,
+
+    /// Path to a TOML config file
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub cmd: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run one of the services
+    #[command(subcommand)]
+    Serve(ServeCommand),
+
+    /// Matrix operations
+    #[command(subcommand)]
+    Matrix(MatrixCommand),
+
+    /// Print the blake3 hash of the input
+    Hash(HashOpts),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ServeCommand {
+    /// TCP chat server
+    Chat(ChatOpts),
+    /// gRPC user service
+    Grpc,
+    /// URL shortener backed by postgres
+    Shortener(ShortenerOpts),
+    /// REST demo service
+    Rest,
+}
+
+#[derive(Debug, Args)]
+pub struct ChatOpts {
+    /// Address for the WebSocket gateway, overrides the config file
+    #[arg(long)]
+    pub ws_listen: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct ShortenerOpts {
+    /// Postgres connection url, overrides the config file
+    #[arg(long)]
+    pub db_url: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MatrixCommand {
+    /// Multiply two matrices given as ROWxCOL:v1,v2,...
+    Multiply {
+        #[arg(long)]
+        lhs: matrix::Matrix<i64>,
+        #[arg(long)]
+        rhs: matrix::Matrix<i64>,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct HashOpts {
+    /// Text to hash, read from stdin when omitted
+    pub input: Option<String>,
+}
+
+impl Opts {
+    pub fn load_config(&self) -> Result<AppConfig> {
+        match &self.config {
+            Some(path) => AppConfig::load(path),
+            None => Ok(AppConfig::default()),
+        }
+    }
+
+    // command line flag wins over the config file, which wins over the default
+    pub fn log_level(&self, config: &AppConfig) -> Result<LevelFilter> {
+        if let Some(level) = self.log_level {
+            return Ok(level);
+        }
+
+        match &config.log_level {
+            Some(level) => level
+                .parse()
+                .map_err(|_| anyhow!("Invalid log level in config file: {}", level)),
+            None => Ok(LevelFilter::INFO),
+        }
+    }
+
+    pub async fn run(self, config: AppConfig) -> Result<()> {
+        let listen = |from_config: Option<String>, default: &str| {
+            self.listen
+                .clone()
+                .or(from_config)
+                .unwrap_or_else(|| default.to_string())
+        };
+
+        match &self.cmd {
+            Command::Serve(ServeCommand::Chat(opts)) => {
+                let addr = listen(config.chat.listen, chat::DEFAULT_ADDR);
+                let history = config.chat.history.unwrap_or(chat::DEFAULT_HISTORY);
+                let mut state = chat::State::new(config.chat.overflow)
+                    .with_history(history)
+                    .with_rate_limit(config.chat.rate_limit)
+                    .with_keepalive(config.chat.keepalive)
+                    .with_resume_grace(
+                        config
+                            .chat
+                            .resume_secs
+                            .map_or(chat::DEFAULT_RESUME_GRACE, Duration::from_secs),
+                    );
+                if let Some(dir) = config.chat.log_dir {
+                    state = state.with_log(chat::ChatLog::open(dir)?)?;
+                }
+                if let Some(secret) = &config.chat.admin_secret {
+                    state = state.with_admin_secret(secret);
+                }
+                if let Some(path) = config.chat.bans_file {
+                    state = state.with_bans(chat::Bans::load(path)?);
+                }
+                if let Some(path) = config.chat.accounts_file {
+                    state = state.with_accounts(chat::Accounts::load(path)?);
+                }
+                let plugins = &config.chat.plugins;
+                if !plugins.enabled.is_empty() {
+                    let timeout = plugins
+                        .timeout_secs
+                        .map_or(chat::DEFAULT_PLUGIN_TIMEOUT, Duration::from_secs);
+                    let mut registered = chat::Plugins::new(timeout);
+                    for name in &plugins.enabled {
+                        registered = registered.with_plugin(chat::bot(name)?);
+                    }
+                    state = state.with_plugins(registered);
+                }
+                if let Some(federation) = &config.chat.federation {
+                    let mut fed = chat::Federation::new(&federation.name);
+                    if let Some(secret) = &federation.secret {
+                        fed = fed.with_secret(secret);
+                    }
+                    state = state.with_federation(fed);
+                }
+                let metrics = &config.chat.metrics;
+                let prometheus = metrics
+                    .listen
+                    .as_ref()
+                    .map(|_| telemetry::Prometheus::new());
+                let provider = match (&prometheus, &metrics.otlp_endpoint) {
+                    (None, None) => None,
+                    (prometheus, endpoint) => Some(telemetry::meter_provider(
+                        prometheus.as_ref(),
+                        endpoint.as_deref(),
+                    )?),
+                };
+                if let Some(provider) = &provider {
+                    state = state.with_metrics(chat::ChatMetrics::new(&provider.meter("chat")));
+                }
+                let state = Arc::new(state);
+                let deadline = config
+                    .chat
+                    .shutdown_secs
+                    .map_or(chat::SHUTDOWN_DEADLINE, Duration::from_secs);
+
+                // every listener shares the state and stops with it
+                let mut servers = vec![chat::serve_with(&addr, state.clone()).boxed()];
+                if let Some(ws_addr) = opts.ws_listen.clone().or(config.chat.ws_listen) {
+                    let state = state.clone();
+                    servers.push(async move { chat::serve_ws(&ws_addr, state).await }.boxed());
+                }
+                if let Some(grpc_addr) = config.chat.grpc_listen {
+                    let state = state.clone();
+                    servers.push(async move { chat::serve_grpc(&grpc_addr, state).await }.boxed());
+                }
+                if let Some(tls) = config.chat.tls {
+                    let acceptor = chat::acceptor(&tls.cert, &tls.key)?;
+                    let tls_addr = tls
+                        .listen
+                        .unwrap_or_else(|| chat::DEFAULT_TLS_ADDR.to_string());
+                    let state = state.clone();
+                    servers.push(
+                        async move { chat::serve_tls(&tls_addr, state, acceptor).await }.boxed(),
+                    );
+                }
+                if let (Some(addr), Some(prometheus)) = (metrics.listen.clone(), prometheus) {
+                    let state = state.clone();
+                    let closing = async move { state.closing().await };
+                    servers.push(
+                        async move { telemetry::serve_prometheus(&addr, prometheus, closing).await }
+                            .boxed(),
+                    );
+                }
+                if let Some(federation) = config.chat.federation {
+                    if let Some(link_addr) = federation.listen {
+                        let state = state.clone();
+                        servers.push(
+                            async move { chat::serve_links(&link_addr, state).await }.boxed(),
+                        );
+                    }
+                    for peer in federation.peers {
+                        servers.push(chat::link_to(peer, state.clone()).boxed());
+                    }
+                }
+                servers.push(
+                    async {
+                        chat::shutdown_signal().await?;
+                        state.shutdown(deadline).await;
+                        Ok(())
+                    }
+                    .boxed(),
+                );
+                future::try_join_all(servers).await?;
+                // the last OTLP export
+                if let Some(provider) = provider {
+                    provider.shutdown()?;
+                }
+                Ok(())
+            }
+            Command::Serve(ServeCommand::Grpc) => {
+                let addr = listen(config.grpc.listen, grpc::DEFAULT_ADDR);
+                grpc::serve(addr.parse()?).await
+            }
+            Command::Serve(ServeCommand::Shortener(opts)) => {
+                let addr = listen(config.shortener.listen, shortener::DEFAULT_ADDR);
+                let db_url = opts
+                    .db_url
+                    .clone()
+                    .or(config.shortener.db_url)
+                    .unwrap_or_else(|| shortener::DEFAULT_DB_URL.to_string());
+                shortener::serve(&addr, &db_url).await
+            }
+            Command::Serve(ServeCommand::Rest) => {
+                rest::serve(&listen(config.rest.listen, rest::DEFAULT_ADDR)).await
+            }
+            Command::Matrix(MatrixCommand::Multiply { lhs, rhs }) => {
+                let ret = matrix::multiply(lhs, rhs)?;
+                println!("{}", ret);
+                Ok(())
+            }
+            Command::Hash(opts) => {
+                let input = match &opts.input {
+                    Some(input) => input.clone(),
+                    None => {
+                        let mut buf = String::new();
+                        io::stdin().read_to_string(&mut buf)?;
+                        buf
+                    }
+                };
+                println!("{}", blake3::hash(input.as_bytes()));
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opts_should_parse_global_flags_after_subcommand() -> Result<()> {
+        let opts = Opts::try_parse_from([
+            "rust-learning",
+            "serve",
+            "chat",
+            "--listen",
+            "127.0.0.1:4000",
+            "--log-level",
+            "debug",
+        ])?;
+
+        assert!(matches!(
+            opts.cmd,
+            Command::Serve(ServeCommand::Chat(ChatOpts { ws_listen: None }))
+        ));
+        assert_eq!(opts.listen.as_deref(), Some("127.0.0.1:4000"));
+        assert_eq!(opts.log_level, Some(LevelFilter::DEBUG));
+        Ok(())
+    }
+
+    #[test]
+    fn opts_should_parse_matrix_multiply() -> Result<()> {
+        let opts = Opts::try_parse_from([
+            "rust-learning",
+            "matrix",
+            "multiply",
+            "--lhs",
+            "1x2:1,2",
+            "--rhs",
+            "2x1:3,4",
+        ])?;
+
+        let Command::Matrix(MatrixCommand::Multiply { lhs, rhs }) = opts.cmd else {
+            panic!("expected matrix multiply command");
+        };
+        assert_eq!(matrix::multiply(&lhs, &rhs)?.data(), &[11]);
+        Ok(())
+    }
+
+    #[test]
+    fn opts_should_reject_invalid_input() {
+        assert!(Opts::try_parse_from(["rust-learning", "serve", "unknown"]).is_err());
+        assert!(
+            Opts::try_parse_from(["rust-learning", "matrix", "multiply", "--lhs", "1x2:1"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn log_level_should_fall_back_to_config() -> Result<()> {
+        let opts = Opts::try_parse_from(["rust-learning", "hash", "abc"])?;
+
+        let config: AppConfig = "log_level = \"warn\"".parse()?;
+        assert_eq!(opts.log_level(&config)?, LevelFilter::WARN);
+        assert_eq!(opts.log_level(&AppConfig::default())?, LevelFilter::INFO);
+
+        let config: AppConfig = "log_level = \"loud\"".parse()?;
+        assert!(opts.log_level(&config).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,226 @@
+use crate::chat::{Keepalive, Overflow, RateLimit};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfig {
+    pub log_level: Option<String>,
+    pub chat: ChatConfig,
+    pub grpc: ServiceConfig,
+    pub rest: ServiceConfig,
+    pub shortener: ShortenerConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServiceConfig {
+    pub listen: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChatConfig {
+    pub listen: Option<String>,
+    // WebSocket gateway sharing the chat, disabled when unset
+    pub ws_listen: Option<String>,
+    // gRPC ChatService sharing the chat, disabled when unset
+    pub grpc_listen: Option<String>,
+    // what to do with a client whose queue is full
+    pub overflow: Overflow,
+    // per client flood protection
+    pub rate_limit: RateLimit,
+    // idle timeout and pings
+    pub keepalive: Keepalive,
+    // seconds a dropped session is held for a resume, 0 disables resuming
+    pub resume_secs: Option<u64>,
+    // seconds a shutdown waits for queued messages to be sent
+    pub shutdown_secs: Option<u64>,
+    // messages kept per room for replay
+    pub history: Option<usize>,
+    // directory for the daily JSON lines chat log, no log when unset
+    pub log_dir: Option<PathBuf>,
+    // secret for `/admin`, moderation is disabled when unset
+    pub admin_secret: Option<String>,
+    // JSON file the bans are kept in, bans only last until restart when unset
+    pub bans_file: Option<PathBuf>,
+    // JSON file of registered accounts, they only last until restart when unset
+    pub accounts_file: Option<PathBuf>,
+    // encrypted listener next to the plain one, disabled when unset
+    pub tls: Option<TlsConfig>,
+    pub metrics: MetricsConfig,
+    // links to other chat servers sharing the rooms, disabled when unset
+    pub federation: Option<FederationConfig>,
+    pub plugins: PluginsConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub listen: Option<String>,
+    // PEM certificate chain and private key
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FederationConfig {
+    // unique among the linked servers, shown after their users' names
+    pub name: String,
+    // address other servers link to, none are accepted when unset
+    pub listen: Option<String>,
+    // servers to link to, dialed again whenever a link drops
+    #[serde(default)]
+    pub peers: Vec<String>,
+    // all linked servers must share it, when set
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PluginsConfig {
+    // bots to run, any of dice, title and reminder
+    pub enabled: Vec<String>,
+    // seconds a bot may take to answer before it is skipped
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    // address serving the Prometheus text format at /metrics, disabled when unset
+    pub listen: Option<String>,
+    // OpenTelemetry collector to push to over OTLP gRPC, disabled when unset
+    pub otlp_endpoint: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShortenerConfig {
+    pub listen: Option<String>,
+    pub db_url: Option<String>,
+}
+
+impl AppConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        content.parse()
+    }
+}
+
+impl std::str::FromStr for AppConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s).context("Failed to parse config file")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_should_parse() -> Result<()> {
+        let config: AppConfig = r#"
+            log_level = "debug"
+
+            [chat]
+            listen = "127.0.0.1:4000"
+            ws_listen = "127.0.0.1:4001"
+            grpc_listen = "127.0.0.1:4002"
+            overflow = "disconnect"
+            history = 50
+            log_dir = "/var/log/chat"
+            admin_secret = "hunter2"
+            bans_file = "/var/lib/chat/bans.json"
+            accounts_file = "/var/lib/chat/accounts.json"
+            shutdown_secs = 2
+            resume_secs = 30
+
+            [chat.rate_limit]
+            burst = 3
+            per_second = 0.5
+
+            [chat.keepalive]
+            ping_secs = 30
+
+            [chat.tls]
+            cert = "/etc/chat/cert.pem"
+            key = "/etc/chat/key.pem"
+
+            [chat.metrics]
+            listen = "127.0.0.1:9100"
+
+            [chat.plugins]
+            enabled = ["dice", "reminder"]
+
+            [chat.federation]
+            name = "east"
+            peers = ["10.0.0.2:3100", "10.0.0.3:3100"]
+
+            [shortener]
+            db_url = "postgres://localhost/test"
+        "#
+        .parse()?;
+
+        assert_eq!(config.log_level.as_deref(), Some("debug"));
+        assert_eq!(config.chat.listen.as_deref(), Some("127.0.0.1:4000"));
+        assert_eq!(config.chat.ws_listen.as_deref(), Some("127.0.0.1:4001"));
+        assert_eq!(config.chat.grpc_listen.as_deref(), Some("127.0.0.1:4002"));
+        assert_eq!(config.chat.overflow, Overflow::Disconnect);
+        assert_eq!(config.chat.rate_limit.burst, 3);
+        assert_eq!(config.chat.rate_limit.strikes, RateLimit::default().strikes);
+        assert_eq!(config.chat.keepalive.ping_secs, 30);
+        assert_eq!(
+            config.chat.keepalive.idle_secs,
+            Keepalive::default().idle_secs
+        );
+        assert_eq!(config.chat.shutdown_secs, Some(2));
+        assert_eq!(config.chat.resume_secs, Some(30));
+        let tls = config.chat.tls.as_ref().unwrap();
+        assert_eq!(tls.listen, None);
+        assert_eq!(tls.key, PathBuf::from("/etc/chat/key.pem"));
+        assert_eq!(
+            config.chat.accounts_file,
+            Some(PathBuf::from("/var/lib/chat/accounts.json"))
+        );
+        assert_eq!(
+            config.chat.metrics.listen.as_deref(),
+            Some("127.0.0.1:9100")
+        );
+        assert_eq!(config.chat.metrics.otlp_endpoint, None);
+        assert_eq!(config.chat.plugins.enabled, ["dice", "reminder"]);
+        assert_eq!(config.chat.plugins.timeout_secs, None);
+        let federation = config.chat.federation.as_ref().unwrap();
+        assert_eq!(federation.name, "east");
+        assert_eq!(federation.listen, None);
+        assert_eq!(federation.peers, ["10.0.0.2:3100", "10.0.0.3:3100"]);
+        assert_eq!(config.chat.history, Some(50));
+        assert_eq!(config.chat.log_dir, Some(PathBuf::from("/var/log/chat")));
+        assert_eq!(config.chat.admin_secret.as_deref(), Some("hunter2"));
+        assert_eq!(
+            config.chat.bans_file,
+            Some(PathBuf::from("/var/lib/chat/bans.json"))
+        );
+        assert_eq!(config.grpc.listen, None);
+        assert_eq!(
+            config.shortener.db_url.as_deref(),
+            Some("postgres://localhost/test")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn config_should_reject_unknown_fields() {
+        assert!("[chat]\nport = 3000".parse::<AppConfig>().is_err());
+        assert!("[chat]\noverflow = \"block\"".parse::<AppConfig>().is_err());
+    }
+}
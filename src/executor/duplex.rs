@@ -0,0 +1,185 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use bytes::Buf;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// One end of an in-memory socket pair created by [`duplex`].
+///
+/// Bytes written to one end can be read from the other. Dropping or shutting down
+/// an end is seen as EOF by its peer. It only relies on wakers, so it works on any executor.
+#[derive(Debug)]
+pub struct DuplexStream {
+    read: Arc<Mutex<Pipe>>,
+    write: Arc<Mutex<Pipe>>,
+}
+
+#[derive(Debug)]
+struct Pipe {
+    buf: bytes::BytesMut,
+    capacity: usize,
+    closed: bool,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+}
+
+/// Creates a connected pair of in-memory streams, each direction buffering up to `capacity` bytes.
+pub fn duplex(capacity: usize) -> (DuplexStream, DuplexStream) {
+    assert!(capacity > 0, "duplex capacity must be positive");
+
+    let a = Arc::new(Mutex::new(Pipe::new(capacity)));
+    let b = Arc::new(Mutex::new(Pipe::new(capacity)));
+
+    (
+        DuplexStream {
+            read: a.clone(),
+            write: b.clone(),
+        },
+        DuplexStream { read: b, write: a },
+    )
+}
+
+impl Pipe {
+    fn new(capacity: usize) -> Self {
+        Self {
+            buf: bytes::BytesMut::with_capacity(capacity),
+            capacity,
+            closed: false,
+            read_waker: None,
+            write_waker: None,
+        }
+    }
+
+    fn close(&mut self) {
+        self.closed = true;
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+        if let Some(waker) = self.write_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl AsyncRead for DuplexStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let mut pipe = self.read.lock().unwrap();
+
+        if pipe.buf.has_remaining() {
+            let n = pipe.buf.remaining().min(buf.remaining());
+            buf.put_slice(&pipe.buf[..n]);
+            pipe.buf.advance(n);
+            if let Some(waker) = pipe.write_waker.take() {
+                waker.wake();
+            }
+            Poll::Ready(Ok(()))
+        } else if pipe.closed {
+            // nothing written into `buf` signals EOF
+            Poll::Ready(Ok(()))
+        } else {
+            pipe.read_waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl AsyncWrite for DuplexStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut pipe = self.write.lock().unwrap();
+
+        if pipe.closed {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+
+        let n = (pipe.capacity - pipe.buf.len()).min(buf.len());
+        if n == 0 {
+            pipe.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        pipe.buf.extend_from_slice(&buf[..n]);
+        if let Some(waker) = pipe.read_waker.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.write.lock().unwrap().close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for DuplexStream {
+    fn drop(&mut self) {
+        self.read.lock().unwrap().close();
+        self.write.lock().unwrap().close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::{block_on, spawn};
+    use futures::{SinkExt, StreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_util::codec::{Framed, LinesCodec};
+
+    #[test]
+    fn duplex_should_carry_lines_both_ways() {
+        block_on(async {
+            let (client, server) = duplex(64);
+            let mut client = Framed::new(client, LinesCodec::new());
+            let mut server = Framed::new(server, LinesCodec::new());
+
+            client.send("hello").await.unwrap();
+            assert_eq!(server.next().await.unwrap().unwrap(), "hello");
+
+            server.send("world").await.unwrap();
+            assert_eq!(client.next().await.unwrap().unwrap(), "world");
+
+            drop(client);
+            assert!(server.next().await.is_none());
+        });
+    }
+
+    #[test]
+    fn duplex_should_apply_backpressure() {
+        block_on(async {
+            let (mut a, mut b) = duplex(4);
+
+            let writer = spawn(async move {
+                a.write_all(b"0123456789").await.unwrap();
+                a.shutdown().await.unwrap();
+            });
+
+            let mut buf = Vec::new();
+            b.read_to_end(&mut buf).await.unwrap();
+            writer.await;
+            assert_eq!(buf, b"0123456789");
+        });
+    }
+
+    #[test]
+    fn duplex_write_should_fail_after_peer_dropped() {
+        block_on(async {
+            let (mut a, b) = duplex(4);
+            drop(b);
+            assert!(a.write_all(b"x").await.is_err());
+        });
+    }
+}
@@ -0,0 +1,160 @@
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+/// Handle to a spawned task, resolving to the task's output.
+///
+/// Dropping the handle detaches the task, it keeps running to completion.
+#[derive(Debug)]
+pub struct JoinHandle<T> {
+    state: Rc<RefCell<JoinState<T>>>,
+}
+
+#[derive(Debug)]
+pub(crate) struct JoinState<T> {
+    output: Option<T>,
+    finished: bool,
+    waker: Option<Waker>,
+}
+
+impl<T> JoinHandle<T> {
+    pub(crate) fn new() -> (Self, Rc<RefCell<JoinState<T>>>) {
+        let state = Rc::new(RefCell::new(JoinState {
+            output: None,
+            finished: false,
+            waker: None,
+        }));
+        (
+            Self {
+                state: state.clone(),
+            },
+            state,
+        )
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state.borrow().finished
+    }
+}
+
+impl<T> JoinState<T> {
+    pub(crate) fn complete(&mut self, output: T) {
+        self.output = Some(output);
+        self.finished = true;
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.borrow_mut();
+        match state.output.take() {
+            Some(output) => Poll::Ready(output),
+            None if state.finished => panic!("JoinHandle polled after completion"),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[doc(hidden)]
+pub enum MaybeDone<F: Future> {
+    Future(Pin<Box<F>>),
+    Done(Option<F::Output>),
+}
+
+impl<F: Future> MaybeDone<F> {
+    pub fn new(fut: F) -> Self {
+        Self::Future(Box::pin(fut))
+    }
+
+    // returns true once the inner future has completed
+    pub fn poll_done(&mut self, cx: &mut Context<'_>) -> bool {
+        if let Self::Future(fut) = self {
+            match crate::j_ready!(fut.as_mut().poll(cx)) {
+                Poll::Ready(output) => *self = Self::Done(Some(output)),
+                Poll::Pending => return false,
+            }
+        }
+        true
+    }
+
+    pub fn take(&mut self) -> F::Output {
+        match self {
+            Self::Done(output) => output.take().expect("output already taken"),
+            Self::Future(_) => panic!("future not completed"),
+        }
+    }
+}
+
+/// Returns a future that polls every given future concurrently and resolves
+/// to a tuple of their outputs once all of them complete.
+#[macro_export]
+macro_rules! j_join {
+    ($($fut:expr),+ $(,)?) => {
+        $crate::__j_join!([] $($fut,)+)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __j_join {
+    // every recursion step introduces its own hygienic `fut` binding
+    ([$($name:ident = $done:expr,)*] $fut:expr, $($rest:expr,)*) => {
+        $crate::__j_join!([$($name = $done,)* fut = $fut,] $($rest,)*)
+    };
+    ([$($name:ident = $fut:expr,)*]) => {{
+        $(let mut $name = $crate::executor::MaybeDone::new($fut);)*
+        ::std::future::poll_fn(move |cx| {
+            let mut done = true;
+            $(done &= $name.poll_done(cx);)*
+            if done {
+                ::std::task::Poll::Ready(($($name.take(),)*))
+            } else {
+                ::std::task::Poll::Pending
+            }
+        })
+    }};
+}
+
+/// Returns a future that polls the branches in order and resolves with the
+/// body of the first branch whose future completes; the others are dropped.
+///
+/// Patterns must be irrefutable, and bodies run inside the poll so they cannot `.await`.
+#[macro_export]
+macro_rules! j_select {
+    ($($pat:pat = $fut:expr => $body:expr),+ $(,)?) => {
+        $crate::__j_select!([] $($pat = $fut => $body,)+)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __j_select {
+    ([$($name:ident: $p:pat = $f:expr => $b:expr,)*] $pat:pat = $fut:expr => $body:expr, $($rest:tt)*) => {
+        $crate::__j_select!([$($name: $p = $f => $b,)* fut: $pat = $fut => $body,] $($rest)*)
+    };
+    ([$($name:ident: $pat:pat = $fut:expr => $body:expr,)*]) => {{
+        $(let mut $name = ::std::boxed::Box::pin($fut);)*
+        #[allow(irrefutable_let_patterns)]
+        let select = move |cx: &mut ::std::task::Context<'_>| {
+            $(
+                if let ::std::task::Poll::Ready($pat) =
+                    $crate::j_ready!(::std::future::Future::poll($name.as_mut(), cx))
+                {
+                    return ::std::task::Poll::Ready($body);
+                }
+            )*
+            ::std::task::Poll::Pending
+        };
+        ::std::future::poll_fn(select)
+    }};
+}
@@ -0,0 +1,526 @@
+//! A minimal single-threaded executor built on `std::task::Wake`.
+//!
+//! [`Executor::deterministic`] runs with a seeded scheduler and a virtual clock,
+//! so concurrent code replays the exact same interleaving for a given seed.
+
+mod duplex;
+mod join;
+mod my_future;
+mod rng;
+mod task;
+mod timer;
+
+pub use duplex::{duplex, DuplexStream};
+pub use join::{JoinHandle, MaybeDone};
+pub use my_future::MyFuture;
+pub use timer::{sleep, Sleep};
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::{pin, Pin};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use rng::Rng;
+use task::{ReadyQueue, TaskId, TaskWaker};
+use timer::TimerWheel;
+
+// the future passed to `block_on` is scheduled like any other task under this id
+const MAIN: TaskId = TaskId::MAX;
+
+thread_local! {
+    static CURRENT: RefCell<Option<Rc<Core>>> = const { RefCell::new(None) };
+}
+
+#[derive(Debug, Default)]
+pub struct Executor {
+    core: Rc<Core>,
+}
+
+struct Task {
+    future: Pin<Box<dyn Future<Output = ()>>>,
+    waker: Arc<TaskWaker>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Core {
+    tasks: RefCell<HashMap<TaskId, Task>>,
+    next_id: Cell<TaskId>,
+    queue: Arc<ReadyQueue>,
+    timers: RefCell<TimerWheel>,
+    clock: Clock,
+    // picks the next ready task at random when set, otherwise tasks run in FIFO order
+    rng: Option<RefCell<Rng>>,
+}
+
+#[derive(Debug)]
+enum Clock {
+    Real(Instant),
+    // only moves when every task is idle, jumping straight to the next timer
+    Virtual(Cell<Duration>),
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an executor that schedules ready tasks in an order derived from `seed`
+    /// and uses a virtual clock, so sleeps complete instantly but in deadline order.
+    ///
+    /// Wakeups from other threads are not supported; if every task is blocked and
+    /// no timer is pending, `block_on` panics instead of hanging.
+    pub fn deterministic(seed: u64) -> Self {
+        Self {
+            core: Rc::new(Core {
+                clock: Clock::Virtual(Cell::new(Duration::ZERO)),
+                rng: Some(RefCell::new(Rng::new(seed))),
+                ..Core::default()
+            }),
+        }
+    }
+
+    /// Runs `fut` to completion, driving spawned tasks and timers in the meantime.
+    pub fn block_on<F: Future>(&self, fut: F) -> F::Output {
+        let _guard = Enter::new(self.core.clone());
+
+        let mut fut = pin!(fut);
+        let main = TaskWaker::new(MAIN, self.core.queue.clone());
+        let waker = Waker::from(main.clone());
+        let mut cx = Context::from_waker(&waker);
+        waker.wake_by_ref();
+
+        loop {
+            while let Some(id) = self.core.next_ready() {
+                if id == MAIN {
+                    main.reset();
+                    if let Poll::Ready(output) = fut.as_mut().poll(&mut cx) {
+                        return output;
+                    }
+                } else {
+                    self.core.poll_task(id);
+                }
+            }
+
+            self.core.timers.borrow_mut().advance(self.core.now());
+            if self.core.queue.is_empty() {
+                self.core.park();
+            }
+        }
+    }
+
+    pub fn spawn<F>(&self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        self.core.spawn(fut)
+    }
+}
+
+/// Runs `fut` to completion on a fresh executor.
+pub fn block_on<F: Future>(fut: F) -> F::Output {
+    Executor::new().block_on(fut)
+}
+
+/// Time elapsed since the current executor was created, virtual in deterministic mode.
+///
+/// # Panics
+///
+/// Panics when called outside of an executor.
+pub fn now() -> Duration {
+    with_current(|core| core.now())
+}
+
+/// Spawns `fut` onto the current executor.
+///
+/// # Panics
+///
+/// Panics when called outside of an executor.
+pub fn spawn<F>(fut: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    with_current(|core| core.spawn(fut))
+}
+
+impl Default for Core {
+    fn default() -> Self {
+        Self {
+            tasks: RefCell::default(),
+            next_id: Cell::new(0),
+            queue: Arc::default(),
+            timers: RefCell::default(),
+            clock: Clock::Real(Instant::now()),
+            rng: None,
+        }
+    }
+}
+
+impl Core {
+    pub(crate) fn now(&self) -> Duration {
+        match &self.clock {
+            Clock::Real(start) => start.elapsed(),
+            Clock::Virtual(now) => now.get(),
+        }
+    }
+
+    fn next_ready(&self) -> Option<TaskId> {
+        match &self.rng {
+            Some(rng) => self.queue.pop_with(|len| rng.borrow_mut().below(len)),
+            None => self.queue.pop(),
+        }
+    }
+
+    fn spawn<F>(&self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let (handle, state) = JoinHandle::new();
+        let future = Box::pin(async move {
+            let output = fut.await;
+            state.borrow_mut().complete(output);
+        });
+
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+
+        let waker = TaskWaker::new(id, self.queue.clone());
+        Waker::from(waker.clone()).wake_by_ref();
+        self.tasks.borrow_mut().insert(id, Task { future, waker });
+
+        handle
+    }
+
+    fn poll_task(&self, id: TaskId) {
+        // take the task out while polling, so it can spawn without a double borrow
+        let Some(mut task) = self.tasks.borrow_mut().remove(&id) else {
+            return;
+        };
+
+        task.waker.reset();
+        let waker = Waker::from(task.waker.clone());
+        let mut cx = Context::from_waker(&waker);
+        if task.future.as_mut().poll(&mut cx).is_pending() {
+            self.tasks.borrow_mut().insert(id, task);
+        }
+    }
+
+    // sleep until the next timer is due or a waker fires from another thread
+    fn park(&self) {
+        let next_deadline = self.timers.borrow().next_deadline();
+
+        match &self.clock {
+            Clock::Real(_) => {
+                let timeout = next_deadline.map(|deadline| deadline.saturating_sub(self.now()));
+                self.queue.park(timeout);
+            }
+            Clock::Virtual(now) => match next_deadline {
+                Some(deadline) => now.set(deadline.max(now.get())),
+                None => panic!("deadlock: every task is blocked and no timer is pending"),
+            },
+        }
+    }
+}
+
+impl std::fmt::Debug for Task {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Task").field("waker", &self.waker).finish()
+    }
+}
+
+// installs the executor as current for the duration of `block_on`
+struct Enter {
+    prev: Option<Rc<Core>>,
+}
+
+impl Enter {
+    fn new(core: Rc<Core>) -> Self {
+        let prev = CURRENT.with(|current| current.borrow_mut().replace(core));
+        Self { prev }
+    }
+}
+
+impl Drop for Enter {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = self.prev.take());
+    }
+}
+
+pub(crate) fn with_current<R>(f: impl FnOnce(&Core) -> R) -> R {
+    try_with_current(f).expect("must be called from within an executor")
+}
+
+// `None` outside of an executor, or while the thread is shutting down
+pub(crate) fn try_with_current<R>(f: impl FnOnce(&Core) -> R) -> Option<R> {
+    let core = CURRENT
+        .try_with(|current| current.borrow().clone())
+        .ok()
+        .flatten()?;
+    Some(f(&core))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{j_join, j_select};
+    use std::sync::mpsc;
+    use std::thread;
+
+    #[test]
+    fn block_on_should_drive_my_future() {
+        assert_eq!(block_on(MyFuture::new(42)), 42);
+        assert_eq!(block_on(async { MyFuture::new(1).await + 1 }), 2);
+    }
+
+    #[test]
+    fn spawn_should_run_tasks_in_order() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+
+        let ret = block_on({
+            let log = log.clone();
+            async move {
+                let handles = (0..3)
+                    .map(|i| {
+                        let log = log.clone();
+                        spawn(async move {
+                            log.borrow_mut().push(i);
+                            MyFuture::new(i).await * 10
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                let mut sum = 0;
+                for handle in handles {
+                    sum += handle.await;
+                }
+                sum
+            }
+        });
+
+        assert_eq!(ret, 30);
+        assert_eq!(*log.borrow(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn sleep_should_wake_in_deadline_order() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+
+        block_on({
+            let log = log.clone();
+            async move {
+                let start = Instant::now();
+                let handles = [30, 10, 20]
+                    .into_iter()
+                    .map(|ms| {
+                        let log = log.clone();
+                        spawn(async move {
+                            sleep(Duration::from_millis(ms)).await;
+                            log.borrow_mut().push(ms);
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                for handle in handles {
+                    handle.await;
+                }
+                assert!(start.elapsed() >= Duration::from_millis(30));
+            }
+        });
+
+        assert_eq!(*log.borrow(), vec![10, 20, 30]);
+    }
+
+    #[test]
+    fn join_should_wait_for_all() {
+        let (a, b, c) = block_on(j_join!(
+            MyFuture::new(1),
+            async {
+                sleep(Duration::from_millis(5)).await;
+                "b"
+            },
+            async { 3.5 },
+        ));
+
+        assert_eq!((a, b, c), (1, "b", 3.5));
+    }
+
+    #[test]
+    fn select_should_take_first_ready() {
+        let ret = block_on(async {
+            j_select! {
+                _ = sleep(Duration::from_millis(50)) => "slow",
+                v = async {
+                    sleep(Duration::from_millis(5)).await;
+                    MyFuture::new(7).await
+                } => if v == 7 { "fast" } else { "wrong" },
+            }
+            .await
+        });
+
+        assert_eq!(ret, "fast");
+    }
+
+    #[test]
+    fn select_should_drop_losing_sleeps() {
+        let executor = Executor::new();
+        let ret = executor.block_on(async {
+            let mut polls = 0;
+            j_select! {
+                _ = sleep(Duration::from_secs(60)) => "slow",
+                _ = std::future::poll_fn(|cx| {
+                    polls += 1;
+                    if polls < 3 {
+                        cx.waker().wake_by_ref();
+                        return Poll::Pending;
+                    }
+                    Poll::Ready(())
+                }) => "fast",
+            }
+            .await
+        });
+
+        assert_eq!(ret, "fast");
+        assert_eq!(executor.core.timers.borrow().len(), 0);
+    }
+
+    #[test]
+    fn waker_should_work_across_threads() {
+        let (tx, rx) = mpsc::channel::<Waker>();
+        let flag = Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+        let handle = {
+            let flag = flag.clone();
+            thread::spawn(move || {
+                let waker = rx.recv().unwrap();
+                thread::sleep(Duration::from_millis(10));
+                flag.store(true, std::sync::atomic::Ordering::SeqCst);
+                waker.wake();
+            })
+        };
+
+        let mut sent = Some(tx);
+        block_on(std::future::poll_fn(|cx| {
+            if flag.load(std::sync::atomic::Ordering::SeqCst) {
+                return Poll::Ready(());
+            }
+            if let Some(tx) = sent.take() {
+                tx.send(cx.waker().clone()).unwrap();
+            }
+            Poll::Pending
+        }));
+
+        handle.join().unwrap();
+    }
+
+    // records the order in which interleaved tasks make progress
+    fn interleaving(executor: Executor) -> Vec<(usize, usize)> {
+        executor.block_on(async {
+            let log = Rc::new(RefCell::new(Vec::new()));
+            let handles = (0..4)
+                .map(|task| {
+                    let log = log.clone();
+                    spawn(async move {
+                        for step in 0..3 {
+                            log.borrow_mut().push((task, step));
+                            MyFuture::new(step).await;
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            for handle in handles {
+                handle.await;
+            }
+            log.take()
+        })
+    }
+
+    #[test]
+    fn deterministic_should_replay_same_seed() {
+        let first = interleaving(Executor::deterministic(7));
+        assert_eq!(first.len(), 12);
+        assert_eq!(first, interleaving(Executor::deterministic(7)));
+
+        let schedules = (0..16)
+            .map(|seed| interleaving(Executor::deterministic(seed)))
+            .collect::<std::collections::HashSet<_>>();
+        assert!(schedules.len() > 1);
+    }
+
+    #[test]
+    fn deterministic_should_jump_virtual_clock() {
+        let real = Instant::now();
+        let (elapsed, order) = Executor::deterministic(1).block_on(async {
+            let order = Rc::new(RefCell::new(Vec::new()));
+            let handles = [3600, 60, 1]
+                .into_iter()
+                .map(|secs| {
+                    let order = order.clone();
+                    spawn(async move {
+                        sleep(Duration::from_secs(secs)).await;
+                        order.borrow_mut().push((secs, now()));
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            for handle in handles {
+                handle.await;
+            }
+            (now(), order.take())
+        });
+
+        assert!(real.elapsed() < Duration::from_secs(1));
+        assert_eq!(elapsed, Duration::from_secs(3600));
+        assert_eq!(
+            order,
+            vec![
+                (1, Duration::from_secs(1)),
+                (60, Duration::from_secs(60)),
+                (3600, Duration::from_secs(3600)),
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "deadlock")]
+    fn deterministic_should_detect_deadlock() {
+        Executor::deterministic(0).block_on(std::future::pending::<()>());
+    }
+
+    #[test]
+    fn deterministic_should_detect_deadlock_after_select() {
+        let executor = Executor::deterministic(0);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            executor.block_on(async {
+                j_select! {
+                    _ = sleep(Duration::from_secs(60)) => {},
+                    _ = MyFuture::new(0) => {},
+                }
+                .await;
+                std::future::pending::<()>().await
+            })
+        }));
+
+        let panic = result.unwrap_err();
+        let message = panic.downcast_ref::<&str>().copied().unwrap_or_default();
+        assert!(
+            message.contains("deadlock"),
+            "unexpected panic: {}",
+            message
+        );
+        // the dropped sleep must not have moved the clock
+        assert_eq!(executor.core.now(), Duration::ZERO);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be called from within an executor")]
+    fn spawn_should_panic_outside_executor() {
+        spawn(async {});
+    }
+}
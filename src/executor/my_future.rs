@@ -0,0 +1,31 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Resolves to `val` on its second poll, waking itself after the first one.
+#[derive(Debug)]
+pub struct MyFuture {
+    polled: bool,
+    val: usize,
+}
+
+impl MyFuture {
+    pub fn new(val: usize) -> Self {
+        Self { polled: false, val }
+    }
+}
+
+impl Future for MyFuture {
+    type Output = usize;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.polled {
+            Poll::Ready(self.val)
+        } else {
+            self.polled = true;
+            // wake up the waker
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::Wake;
+use std::time::Duration;
+
+pub(crate) type TaskId = usize;
+
+// ready task ids, shared with wakers which may fire from any thread
+#[derive(Debug, Default)]
+pub(crate) struct ReadyQueue {
+    ready: Mutex<VecDeque<TaskId>>,
+    cond: Condvar,
+}
+
+impl ReadyQueue {
+    pub(crate) fn push(&self, id: TaskId) {
+        self.ready.lock().unwrap().push_back(id);
+        self.cond.notify_one();
+    }
+
+    pub(crate) fn pop(&self) -> Option<TaskId> {
+        self.ready.lock().unwrap().pop_front()
+    }
+
+    // remove the entry at the index chosen by `pick`, given the queue length
+    pub(crate) fn pop_with(&self, pick: impl FnOnce(usize) -> usize) -> Option<TaskId> {
+        let mut ready = self.ready.lock().unwrap();
+        if ready.is_empty() {
+            return None;
+        }
+        let idx = pick(ready.len());
+        ready.remove(idx)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.ready.lock().unwrap().is_empty()
+    }
+
+    // block the executor thread until a task is woken or the timeout elapses
+    pub(crate) fn park(&self, timeout: Option<Duration>) {
+        let ready = self.ready.lock().unwrap();
+        if !ready.is_empty() {
+            return;
+        }
+
+        match timeout {
+            Some(timeout) => drop(self.cond.wait_timeout(ready, timeout).unwrap()),
+            None => drop(self.cond.wait(ready).unwrap()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct TaskWaker {
+    id: TaskId,
+    scheduled: AtomicBool,
+    queue: Arc<ReadyQueue>,
+}
+
+impl TaskWaker {
+    pub(crate) fn new(id: TaskId, queue: Arc<ReadyQueue>) -> Arc<Self> {
+        Arc::new(Self {
+            id,
+            scheduled: AtomicBool::new(false),
+            queue,
+        })
+    }
+
+    // called right before polling, so a wake during the poll schedules the task again
+    pub(crate) fn reset(&self) {
+        self.scheduled.store(false, Ordering::Release);
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        // a task sits in the queue at most once no matter how often it is woken
+        if !self.scheduled.swap(true, Ordering::AcqRel) {
+            self.queue.push(self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::task::Waker;
+
+    #[test]
+    fn waker_should_schedule_task_once() {
+        let queue = Arc::new(ReadyQueue::default());
+        let task = TaskWaker::new(1, queue.clone());
+        let waker = Waker::from(task.clone());
+
+        waker.wake_by_ref();
+        waker.wake_by_ref();
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(queue.pop(), None);
+
+        task.reset();
+        waker.wake();
+        assert_eq!(queue.pop(), Some(1));
+    }
+}
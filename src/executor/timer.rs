@@ -0,0 +1,240 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use super::{try_with_current, with_current};
+
+const SLOTS: usize = 256;
+const TICK: Duration = Duration::from_millis(1);
+
+// hashed timing wheel: a timer lives in slot `deadline % SLOTS`,
+// so advancing the clock only visits the slots it passes over
+#[derive(Debug)]
+pub(crate) struct TimerWheel {
+    slots: Vec<Vec<Entry>>,
+    elapsed: u64,
+    len: usize,
+    next_id: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    id: u64,
+    deadline: u64,
+    waker: Waker,
+}
+
+// where a registered timer lives, so it can be found again without a scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TimerKey {
+    id: u64,
+    slot: usize,
+}
+
+impl Default for TimerWheel {
+    fn default() -> Self {
+        Self {
+            slots: (0..SLOTS).map(|_| Vec::new()).collect(),
+            elapsed: 0,
+            len: 0,
+            next_id: 0,
+        }
+    }
+}
+
+impl TimerWheel {
+    pub(crate) fn insert(&mut self, deadline: Duration, waker: Waker) -> TimerKey {
+        // round up so a timer never fires before its deadline
+        let deadline = (deadline.as_nanos().div_ceil(TICK.as_nanos()) as u64).max(self.elapsed);
+        let key = TimerKey {
+            id: self.next_id,
+            slot: deadline as usize % SLOTS,
+        };
+        self.next_id += 1;
+        self.slots[key.slot].push(Entry {
+            id: key.id,
+            deadline,
+            waker,
+        });
+        self.len += 1;
+        key
+    }
+
+    // swaps in the waker of the latest poll, false once the timer has fired
+    pub(crate) fn update(&mut self, key: TimerKey, waker: &Waker) -> bool {
+        match self.slots[key.slot]
+            .iter_mut()
+            .find(|entry| entry.id == key.id)
+        {
+            Some(entry) => {
+                if !entry.waker.will_wake(waker) {
+                    entry.waker = waker.clone();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: TimerKey) {
+        let slot = &mut self.slots[key.slot];
+        if let Some(i) = slot.iter().position(|entry| entry.id == key.id) {
+            slot.swap_remove(i);
+            self.len -= 1;
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    // wake every timer whose deadline is at or before `now`, returns how many fired
+    pub(crate) fn advance(&mut self, now: Duration) -> usize {
+        let now = (now.as_nanos() / TICK.as_nanos()) as u64;
+        if now < self.elapsed || self.len == 0 {
+            self.elapsed = self.elapsed.max(now);
+            return 0;
+        }
+
+        let span = (now - self.elapsed + 1).min(SLOTS as u64);
+        let mut fired = 0;
+        for tick in self.elapsed..self.elapsed + span {
+            let slot = &mut self.slots[tick as usize % SLOTS];
+            let mut i = 0;
+            while i < slot.len() {
+                if slot[i].deadline <= now {
+                    slot.swap_remove(i).waker.wake();
+                    fired += 1;
+                } else {
+                    i += 1;
+                }
+            }
+        }
+
+        self.len -= fired;
+        self.elapsed = now;
+        fired
+    }
+
+    pub(crate) fn next_deadline(&self) -> Option<Duration> {
+        self.slots
+            .iter()
+            .flatten()
+            .map(|entry| entry.deadline)
+            .min()
+            .map(|deadline| Duration::from_nanos(deadline * TICK.as_nanos() as u64))
+    }
+}
+
+/// Future returned by [`sleep`], resolves once the executor clock passes its deadline.
+#[derive(Debug)]
+pub struct Sleep {
+    deadline: Duration,
+    // set once the first pending poll registered a timer
+    timer: Option<TimerKey>,
+}
+
+/// Sleeps for `duration` on the current executor.
+///
+/// # Panics
+///
+/// Panics when called outside of an executor.
+pub fn sleep(duration: Duration) -> Sleep {
+    let now = with_current(|core| core.now());
+    Sleep {
+        deadline: now + duration,
+        timer: None,
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        with_current(|core| {
+            if core.now() >= self.deadline {
+                self.timer = None;
+                return Poll::Ready(());
+            }
+
+            let mut timers = core.timers.borrow_mut();
+            match self.timer {
+                Some(key) if timers.update(key, cx.waker()) => {}
+                _ => self.timer = Some(timers.insert(self.deadline, cx.waker().clone())),
+            }
+            Poll::Pending
+        })
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        // a sleep dropped early, e.g. the loser of a select, must not leave a stale timer behind
+        if let Some(key) = self.timer.take() {
+            try_with_current(|core| {
+                if let Ok(mut timers) = core.timers.try_borrow_mut() {
+                    timers.remove(key);
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::Wake;
+
+    struct CountWaker(AtomicUsize);
+
+    impl Wake for CountWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn timer_wheel_should_fire_due_timers() {
+        let counter = Arc::new(CountWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(counter.clone());
+        let mut wheel = TimerWheel::default();
+
+        wheel.insert(Duration::from_millis(5), waker.clone());
+        wheel.insert(Duration::from_millis(10), waker.clone());
+        // lands in the same slot as the 10ms timer but one round later
+        wheel.insert(Duration::from_millis(10 + SLOTS as u64), waker);
+        assert_eq!(wheel.next_deadline(), Some(Duration::from_millis(5)));
+
+        assert_eq!(wheel.advance(Duration::from_millis(4)), 0);
+        assert_eq!(wheel.advance(Duration::from_millis(5)), 1);
+        assert_eq!(wheel.next_deadline(), Some(Duration::from_millis(10)));
+
+        assert_eq!(wheel.advance(Duration::from_millis(100)), 1);
+        assert_eq!(wheel.advance(Duration::from_secs(10)), 1);
+        assert_eq!(wheel.next_deadline(), None);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn timer_wheel_should_remove_and_update_timers() {
+        let counter = Arc::new(CountWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(counter.clone());
+        let mut wheel = TimerWheel::default();
+
+        let first = wheel.insert(Duration::from_millis(5), waker.clone());
+        let second = wheel.insert(Duration::from_millis(5), waker.clone());
+        wheel.remove(first);
+        assert_eq!(wheel.len(), 1);
+        assert!(!wheel.update(first, &waker));
+        assert!(wheel.update(second, &waker));
+
+        assert_eq!(wheel.advance(Duration::from_millis(5)), 1);
+        assert!(!wheel.update(second, &waker));
+        assert_eq!(wheel.len(), 0);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,48 @@
+use crate::pb::{
+    user_service_server::{UserService, UserServiceServer},
+    CreateUserReq, GetUserReq, User,
+};
+use anyhow::Result;
+use std::net::SocketAddr;
+use tonic::{transport::Server, Request, Response, Status};
+use tracing::info;
+
+pub const DEFAULT_ADDR: &str = "[::1]:8081";
+
+pub struct UserServer;
+
+#[tonic::async_trait]
+impl UserService for UserServer {
+    async fn get_user(&self, req: Request<GetUserReq>) -> Result<Response<User>, Status> {
+        let input = req.into_inner();
+        info!("input: {:?}", input);
+        Ok(Response::new(User::new(
+            input.id,
+            "jrmaarcco",
+            "12345678901",
+            "jrmaarcco@example.com",
+        )))
+    }
+
+    async fn create_user(&self, req: Request<CreateUserReq>) -> Result<Response<User>, Status> {
+        let input = req.into_inner();
+        info!("input: {:?}", input);
+        Ok(Response::new(User::new(
+            1,
+            &input.name,
+            &input.phone,
+            &input.email,
+        )))
+    }
+}
+
+pub async fn serve(addr: SocketAddr) -> Result<()> {
+    info!("User Service listening on {}", addr);
+
+    Server::builder()
+        .add_service(UserServiceServer::new(UserServer))
+        .serve(addr)
+        .await?;
+
+    Ok(())
+}
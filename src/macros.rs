@@ -0,0 +1,384 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static BREADCRUMBS: AtomicBool = AtomicBool::new(false);
+
+/// Makes `j_try!` attach an `at file:line` context to `anyhow::Error`s at every
+/// propagation point, so the error printed by `main` shows the whole path.
+pub fn set_breadcrumbs(enabled: bool) {
+    BREADCRUMBS.store(enabled, Ordering::Relaxed);
+}
+
+pub fn breadcrumbs_enabled() -> bool {
+    BREADCRUMBS.load(Ordering::Relaxed)
+}
+
+/// Unwraps an `Ok`/`Some` value or returns early with the error converted via `Into`.
+///
+/// - `j_try!(expr)` works like `?` on a `Result`.
+/// - `j_try!(expr, "loading user {}", id)` attaches context (`Result` or `Option`), returning `anyhow::Error`.
+/// - `j_try!(opt, else err)` unwraps an `Option`, returning `err` when it is `None`.
+///
+/// Every early return is logged at debug level with the file and line of the call site.
+#[macro_export]
+macro_rules! j_try {
+    ($expr:expr, else $err:expr $(,)?) => {
+        match $expr {
+            Some(val) => val,
+            None => $crate::__j_try_return!($err),
+        }
+    };
+    ($expr:expr, $($arg:tt)+) => {
+        match $crate::__private::anyhow::Context::with_context($expr, || format!($($arg)+)) {
+            Ok(val) => val,
+            Err(err) => $crate::__j_try_return!(err),
+        }
+    };
+    ($expr:expr $(,)?) => {
+        match $expr {
+            Ok(val) => val,
+            Err(err) => $crate::__j_try_return!(err),
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __j_try_return {
+    ($err:expr) => {{
+        #[allow(unused_imports)]
+        use $crate::__private::PropagateAny as _;
+
+        let err = $err;
+        $crate::__private::tracing::debug!(
+            file = file!(),
+            line = line!(),
+            error = %format_args!("{:#}", err),
+            "j_try! early return"
+        );
+        return Err($crate::__private::Propagate(err)
+            .breadcrumb(file!(), line!())
+            .into());
+    }};
+}
+
+#[doc(hidden)]
+pub mod __private {
+    pub use anyhow;
+    pub use tracing;
+
+    pub struct Propagate<E>(pub E);
+
+    impl Propagate<anyhow::Error> {
+        // inherent method wins over `PropagateAny`, so only anyhow errors get breadcrumbs
+        pub fn breadcrumb(self, file: &'static str, line: u32) -> anyhow::Error {
+            if super::breadcrumbs_enabled() {
+                self.0.context(format!("at {}:{}", file, line))
+            } else {
+                self.0
+            }
+        }
+    }
+
+    // adapts a capacity-less constructor to the `with_capacity` shape used by the collection macros
+    pub fn no_capacity<T>(new: fn() -> T) -> impl FnOnce(usize) -> T {
+        move |_| new()
+    }
+
+    pub trait PropagateAny<E> {
+        fn breadcrumb(self, file: &'static str, line: u32) -> E;
+    }
+
+    impl<E> PropagateAny<E> for Propagate<E> {
+        fn breadcrumb(self, _file: &'static str, _line: u32) -> E {
+            self.0
+        }
+    }
+}
+
+/// Builds a `Vec` the same way `vec!` does.
+///
+/// `j_vec![try a, b, c]` takes `Result` elements and returns the first error,
+/// or `Ok` with the collected values. The other collection macros share this form.
+#[macro_export]
+macro_rules! j_vec {
+    () => {
+        ::std::vec::Vec::new()
+    };
+    (try $($x:expr),* $(,)?) => {
+        $crate::__j_try_collect!(::std::vec::Vec::with_capacity, push; $($x),*)
+    };
+    ($elem:expr; $n:expr) => {
+        ::std::vec::from_elem($elem, $n)
+    };
+    ($($x:expr),+ $(,)?) => {{
+        <[_]>::into_vec(::std::boxed::Box::new([$($x),*]))
+    }}
+}
+
+/// Builds a `HashMap` from `key => value` pairs, pre-sized to the number of pairs.
+#[macro_export]
+macro_rules! j_map {
+    (try $($k:expr => $v:expr),* $(,)?) => {
+        $crate::__j_try_collect!(::std::collections::HashMap::with_capacity, insert; $($k => $v),*)
+    };
+    ($($k:expr => $v:expr),* $(,)?) => {
+        $crate::__j_collect!(::std::collections::HashMap::with_capacity, insert; $($k => $v),*)
+    };
+}
+
+/// Builds a `BTreeMap` from `key => value` pairs.
+#[macro_export]
+macro_rules! j_btree {
+    (try $($k:expr => $v:expr),* $(,)?) => {
+        $crate::__j_try_collect!(
+            $crate::__private::no_capacity(::std::collections::BTreeMap::new), insert; $($k => $v),*
+        )
+    };
+    ($($k:expr => $v:expr),* $(,)?) => {
+        $crate::__j_collect!(
+            $crate::__private::no_capacity(::std::collections::BTreeMap::new), insert; $($k => $v),*
+        )
+    };
+}
+
+/// Builds a `HashSet`, pre-sized to the number of elements.
+#[macro_export]
+macro_rules! j_set {
+    (try $($x:expr),* $(,)?) => {
+        $crate::__j_try_collect!(::std::collections::HashSet::with_capacity, insert; $($x),*)
+    };
+    ($($x:expr),* $(,)?) => {
+        $crate::__j_collect!(::std::collections::HashSet::with_capacity, insert; $($x),*)
+    };
+}
+
+/// Builds a `VecDeque`, pre-sized to the number of elements.
+#[macro_export]
+macro_rules! j_deque {
+    (try $($x:expr),* $(,)?) => {
+        $crate::__j_try_collect!(::std::collections::VecDeque::with_capacity, push_back; $($x),*)
+    };
+    ($($x:expr),* $(,)?) => {
+        $crate::__j_collect!(::std::collections::VecDeque::with_capacity, push_back; $($x),*)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __j_count {
+    (@unit $x:expr) => {
+        ()
+    };
+    ($($x:expr),*) => {
+        <[()]>::len(&[$($crate::__j_count!(@unit $x)),*])
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __j_collect {
+    ($new:expr, $add:ident; $($k:expr => $v:expr),*) => {{
+        const CAP: usize = $crate::__j_count!($($k),*);
+        #[allow(unused_mut)]
+        let mut coll = $new(CAP);
+        $(coll.$add($k, $v);)*
+        coll
+    }};
+    ($new:expr, $add:ident; $($x:expr),*) => {{
+        const CAP: usize = $crate::__j_count!($($x),*);
+        #[allow(unused_mut)]
+        let mut coll = $new(CAP);
+        $(coll.$add($x);)*
+        coll
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __j_try_collect {
+    ($new:expr, $add:ident; $($k:expr => $v:expr),*) => {{
+        const CAP: usize = $crate::__j_count!($($k),*);
+        #[allow(unused_mut)]
+        let mut coll = $new(CAP);
+        'collect: {
+            $(match $v {
+                Ok(val) => {
+                    coll.$add($k, val);
+                }
+                Err(err) => break 'collect Err(err),
+            })*
+            Ok(coll)
+        }
+    }};
+    ($new:expr, $add:ident; $($x:expr),*) => {{
+        const CAP: usize = $crate::__j_count!($($x),*);
+        #[allow(unused_mut)]
+        let mut coll = $new(CAP);
+        'collect: {
+            $(match $x {
+                Ok(val) => {
+                    coll.$add(val);
+                }
+                Err(err) => break 'collect Err(err),
+            })*
+            Ok(coll)
+        }
+    }};
+}
+
+/// Forwards the result of a poll, mirroring `std::task::ready!` without the early return.
+#[macro_export]
+macro_rules! j_ready {
+    ($expr:expr) => {
+        match $expr {
+            ::std::task::Poll::Ready(v) => ::std::task::Poll::Ready(v),
+            ::std::task::Poll::Pending => ::std::task::Poll::Pending,
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Result};
+    use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+    use std::task::Poll;
+
+    fn parse(s: &str) -> Result<i32> {
+        Ok(j_try!(s.parse::<i32>()))
+    }
+
+    fn fail() -> Result<i32> {
+        let val: i32 = j_try!(Err(anyhow!("failed")));
+        Ok(val)
+    }
+
+    fn load_user(users: &HashMap<u32, String>, id: u32) -> Result<String> {
+        let name = j_try!(users.get(&id), "loading user {}", id);
+        Ok(name.clone())
+    }
+
+    fn find_user(users: &HashMap<u32, String>, id: u32) -> Result<String, String> {
+        let name = j_try!(users.get(&id), else format!("user {} not found", id));
+        Ok(name.clone())
+    }
+
+    fn parse_with_context(s: &str) -> Result<i32> {
+        Ok(j_try!(s.parse::<i32>(), "parsing {:?}", s))
+    }
+
+    #[test]
+    fn j_try_should_work() {
+        assert_eq!(parse("42").unwrap(), 42);
+        assert!(parse("x").is_err());
+        assert_eq!(fail().unwrap_err().to_string(), "failed");
+    }
+
+    #[test]
+    fn j_try_should_attach_context() {
+        let users = HashMap::from([(1, "alice".to_string())]);
+        assert_eq!(load_user(&users, 1).unwrap(), "alice");
+        assert_eq!(
+            load_user(&users, 2).unwrap_err().to_string(),
+            "loading user 2"
+        );
+
+        let err = parse_with_context("x").unwrap_err();
+        assert_eq!(err.to_string(), "parsing \"x\"");
+        assert_eq!(
+            err.root_cause().to_string(),
+            "invalid digit found in string"
+        );
+    }
+
+    #[test]
+    fn j_try_should_support_option_with_error() {
+        let users = HashMap::from([(1, "alice".to_string())]);
+        assert_eq!(find_user(&users, 1).unwrap(), "alice");
+        assert_eq!(find_user(&users, 2).unwrap_err(), "user 2 not found");
+    }
+
+    #[test]
+    fn j_vec_should_work() {
+        let empty: Vec<i32> = j_vec![];
+        assert!(empty.is_empty());
+        assert_eq!(j_vec![0; 3], vec![0, 0, 0]);
+        assert_eq!(j_vec![1, 2, 3,], vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn j_vec_try_should_work() {
+        let val: Result<Vec<i32>, _> = j_vec![try "1".parse(), "2".parse(), "3".parse(),];
+        let val = val.unwrap();
+        assert_eq!(val, vec![1, 2, 3]);
+        assert_eq!(val.capacity(), 3);
+
+        let val: Result<Vec<i32>, _> = j_vec![try "1".parse(), "x".parse(), "3".parse()];
+        assert!(val.is_err());
+    }
+
+    #[test]
+    fn j_map_should_work() {
+        let empty: HashMap<&str, i32> = j_map! {};
+        assert!(empty.is_empty());
+
+        let map = j_map! {
+            "a" => 1,
+            "b" => 2,
+            "c" => 3,
+        };
+        assert_eq!(map.len(), 3);
+        assert_eq!(map["b"], 2);
+        assert!(map.capacity() >= 3);
+
+        let map: Result<HashMap<_, i32>, _> = j_map! { try "a" => "1".parse(), "b" => "2".parse() };
+        assert_eq!(map.unwrap()["a"], 1);
+
+        let map: Result<HashMap<_, i32>, _> = j_map! { try "a" => "1".parse(), "b" => "x".parse() };
+        assert!(map.is_err());
+    }
+
+    #[test]
+    fn j_btree_should_work() {
+        let map = j_btree! { 3 => "c", 1 => "a", 2 => "b" };
+        assert_eq!(map.values().copied().collect::<Vec<_>>(), ["a", "b", "c"]);
+
+        let map: Result<BTreeMap<_, u8>, _> =
+            j_btree! { try 1 => "1".parse(), 2 => "256".parse(), };
+        assert!(map.is_err());
+    }
+
+    #[test]
+    fn j_set_should_work() {
+        let set = j_set![1, 2, 2, 3,];
+        assert_eq!(set.len(), 3);
+        assert!(set.capacity() >= 4);
+        assert!(set.contains(&2));
+
+        let set: Result<HashSet<i32>, _> = j_set![try "1".parse(), "1".parse()];
+        assert_eq!(set.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn j_deque_should_work() {
+        let mut deque = j_deque![1, 2, 3];
+        assert!(deque.capacity() >= 3);
+        assert_eq!(deque.pop_front(), Some(1));
+        assert_eq!(deque.pop_back(), Some(3));
+
+        let deque: Result<VecDeque<i32>, _> = j_deque![try "1".parse(), "?".parse()];
+        assert!(deque.is_err());
+    }
+
+    #[test]
+    fn j_count_should_be_const() {
+        const N: usize = __j_count!(1, "a", vec![1, 2], (1, 2));
+        assert_eq!(N, 4);
+        assert_eq!(__j_count!(), 0);
+    }
+
+    #[test]
+    fn j_ready_should_work() {
+        assert_eq!(j_ready!(Poll::Ready(1)), Poll::Ready(1));
+        assert_eq!(j_ready!(Poll::<i32>::Pending), Poll::Pending);
+    }
+}
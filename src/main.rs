@@ -1,3 +1,35 @@
-fn main() {
-    println!("hello world")
+use clap::Parser;
+use rust_learning::{cli::Opts, telemetry};
+use std::process::ExitCode;
+
+// exit code for invalid configuration, matching clap's exit code for usage errors
+const EXIT_USAGE: u8 = 2;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let opts = Opts::parse();
+
+    let (config, level) = match opts
+        .load_config()
+        .and_then(|config| opts.log_level(&config).map(|level| (config, level)))
+    {
+        Ok(ret) => ret,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    if let Err(e) = telemetry::init(level) {
+        eprintln!("Error: {:#}", e);
+        return ExitCode::FAILURE;
+    }
+
+    match opts.run(config).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
 }
@@ -0,0 +1,386 @@
+use std::fmt::{self, Formatter};
+use std::num::NonZeroUsize;
+use std::ops::{Add, AddAssign, Deref, Mul};
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+
+use anyhow::{anyhow, Result};
+
+// pool size when the number of cores is unknown
+const NUM_THREADS: usize = 4;
+
+pub struct Vector<T> {
+    data: Vec<T>,
+}
+
+impl<T> Deref for Vector<T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.data
+    }
+}
+
+impl<T> Vector<T> {
+    pub fn new(data: impl Into<Vec<T>>) -> Self {
+        Self { data: data.into() }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Matrix<T> {
+    data: Vec<T>,
+    row: usize,
+    col: usize,
+}
+
+impl<T> Matrix<T> {
+    pub fn new(data: impl Into<Vec<T>>, row: usize, col: usize) -> Self {
+        Self {
+            data: data.into(),
+            row,
+            col,
+        }
+    }
+
+    pub fn row(&self) -> usize {
+        self.row
+    }
+
+    pub fn col(&self) -> usize {
+        self.col
+    }
+
+    pub fn data(&self) -> &[T] {
+        &self.data
+    }
+
+    pub fn transpose(&self) -> Self
+    where
+        T: Copy,
+    {
+        let data = (0..self.col)
+            .flat_map(|j| self.data.iter().skip(j).step_by(self.col).copied())
+            .collect::<Vec<_>>();
+        Self::new(data, self.col, self.row)
+    }
+}
+
+impl<T> fmt::Display for Matrix<T>
+where
+    T: fmt::Display,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{{")?;
+
+        for i in 0..self.row {
+            for j in 0..self.col {
+                write!(f, "{}", self.data[i * self.col + j])?;
+
+                if j != self.col - 1 {
+                    write!(f, ", ")?;
+                }
+            }
+
+            if i != self.row - 1 {
+                write!(f, ", ")?;
+            }
+        }
+
+        write!(f, "}}")?;
+        Ok(())
+    }
+}
+
+impl<T> fmt::Debug for Matrix<T>
+where
+    T: fmt::Display,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Matrix(row={}, col={}, {})", self.row, self.col, self)
+    }
+}
+
+// parse matrix from `{row}x{col}:{v1},{v2},...`, e.g. `2x2:1,2,3,4`
+impl<T> FromStr for Matrix<T>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (shape, values) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Matrix should be formatted as ROWxCOL:v1,v2,..."))?;
+        let (row, col) = shape
+            .split_once('x')
+            .ok_or_else(|| anyhow!("Matrix shape should be formatted as ROWxCOL."))?;
+        let (row, col): (usize, usize) = (row.trim().parse()?, col.trim().parse()?);
+
+        let data = values
+            .split(',')
+            .map(|v| {
+                v.trim()
+                    .parse()
+                    .map_err(|e| anyhow!("Invalid matrix value {:?}: {}", v, e))
+            })
+            .collect::<Result<Vec<T>>>()?;
+
+        if data.len() != row * col {
+            return Err(anyhow!(
+                "Matrix {}x{} expects {} values, got {}.",
+                row,
+                col,
+                row * col,
+                data.len()
+            ));
+        }
+
+        Ok(Self::new(data, row, col))
+    }
+}
+
+// a fixed set of worker threads, created once and reused by every multiply
+pub struct Pool {
+    sender: Option<mpsc::Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+impl Pool {
+    pub fn new(size: usize) -> Self {
+        let (tx, rx) = mpsc::channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+        let workers = (0..size.max(1))
+            .map(|_| {
+                let rx = rx.clone();
+                thread::spawn(move || loop {
+                    // the lock is released before the job runs
+                    let job = match rx.lock() {
+                        Ok(rx) => rx.recv(),
+                        Err(_) => break,
+                    };
+                    match job {
+                        // a panicking job drops its result sender, the caller sees the error and
+                        // the worker lives on
+                        Ok(job) => {
+                            let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                        }
+                        Err(_) => break,
+                    }
+                })
+            })
+            .collect();
+
+        Self {
+            sender: Some(tx),
+            workers,
+        }
+    }
+
+    // shared by `multiply` and `Matrix * Matrix`, one worker per core
+    pub fn global() -> &'static Pool {
+        static POOL: OnceLock<Pool> = OnceLock::new();
+        POOL.get_or_init(|| {
+            let size = thread::available_parallelism().map_or(NUM_THREADS, NonZeroUsize::get);
+            Pool::new(size)
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    fn execute(&self, job: impl FnOnce() + Send + 'static) -> Result<()> {
+        self.sender
+            .as_ref()
+            .ok_or_else(|| anyhow!("Pool is shut down."))?
+            .send(Box::new(job))
+            .map_err(|_| anyhow!("Pool workers are gone."))
+    }
+
+    // each worker gets a block of rows of `a`, dotted with the rows of `b` transposed
+    pub fn multiply<T>(&self, a: &Matrix<T>, b: &Matrix<T>) -> Result<Matrix<T>>
+    where
+        T: Copy + Default + Add<Output = T> + AddAssign + Mul<Output = T> + Send + Sync + 'static,
+    {
+        if a.col != b.row {
+            return Err(anyhow!("Matrix multiply error: a.col != b.row."));
+        }
+
+        let (len, col) = (a.col, b.col);
+        // empty rows dot to zero, and `chunks_exact` takes no zero length
+        if len == 0 {
+            return Ok(Matrix {
+                data: vec![T::default(); a.row * col],
+                row: a.row,
+                col,
+            });
+        }
+        let lhs: Arc<[T]> = a.data.as_slice().into();
+        let rhs: Arc<[T]> = b.transpose().data.into();
+        let block = a.row.div_ceil(self.size()).max(1);
+
+        let (tx, rx) = mpsc::channel();
+        let mut blocks = 0;
+        for start in (0..a.row).step_by(block) {
+            let end = (start + block).min(a.row);
+            let (lhs, rhs, tx) = (lhs.clone(), rhs.clone(), tx.clone());
+            self.execute(move || {
+                let mut out = Vec::with_capacity((end - start) * col);
+                for row in lhs[start * len..end * len].chunks_exact(len) {
+                    out.extend(rhs.chunks_exact(len).map(|col| dot(row, col)));
+                }
+                let _ = tx.send((start, out));
+            })?;
+            blocks += 1;
+        }
+        drop(tx);
+
+        let mut data = vec![T::default(); a.row * col];
+        for _ in 0..blocks {
+            let (start, out) = rx
+                .recv()
+                .map_err(|_| anyhow!("Matrix multiply error: a worker failed."))?;
+            data[start * col..start * col + out.len()].copy_from_slice(&out);
+        }
+
+        Ok(Matrix {
+            data,
+            row: a.row,
+            col,
+        })
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        // workers stop once the channel closes
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl<T> Mul for Matrix<T>
+where
+    T: Copy + Default + Add<Output = T> + AddAssign + Mul<Output = T> + Send + Sync + 'static,
+{
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        multiply(&self, &rhs).expect("Matrix multiply error.")
+    }
+}
+
+pub fn multiply<T>(a: &Matrix<T>, b: &Matrix<T>) -> Result<Matrix<T>>
+where
+    T: Copy + Default + Add<Output = T> + AddAssign + Mul<Output = T> + Send + Sync + 'static,
+{
+    Pool::global().multiply(a, b)
+}
+
+pub fn dot_product<T>(a: Vector<T>, b: Vector<T>) -> Result<T>
+where
+    T: Copy + Default + Add<Output = T> + AddAssign + Mul<Output = T>,
+{
+    if a.len() != b.len() {
+        return Err(anyhow!("Dot product error: a.len() != b.len()."));
+    }
+
+    Ok(dot(&a, &b))
+}
+
+fn dot<T>(a: &[T], b: &[T]) -> T
+where
+    T: Copy + Default + AddAssign + Mul<Output = T>,
+{
+    let mut sum = T::default();
+    for (x, y) in a.iter().zip(b) {
+        sum += *x * *y;
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multiply_should_work() -> Result<()> {
+        let a = Matrix::new([1, 2, 3, 4, 5, 6], 2, 3);
+        let b = Matrix::new([1, 2, 3, 4, 5, 6], 3, 2);
+        let c = multiply(&a, &b)?;
+
+        assert_eq!(c.row(), 2);
+        assert_eq!(c.col(), 2);
+        assert_eq!(c.data(), &[22, 28, 49, 64]);
+        assert_eq!(format!("{:?}", c), "Matrix(row=2, col=2, {22, 28, 49, 64})");
+        Ok(())
+    }
+
+    #[test]
+    fn multiply_should_reject_mismatched_shapes() {
+        let a = Matrix::new([1, 2, 3, 4], 2, 2);
+        let b = Matrix::new([1, 2, 3], 3, 1);
+        assert!(multiply(&a, &b).is_err());
+    }
+
+    #[test]
+    fn pool_should_split_rows_into_blocks() -> Result<()> {
+        let pool = Pool::new(2);
+        let a = Matrix::new([1, 2, 3, 4, 5, 6], 3, 2);
+        let b = Matrix::new([1, 2, 3, 4, 5, 6], 2, 3);
+        let c = pool.multiply(&a, &b)?;
+        assert_eq!(c, Matrix::new([9, 12, 15, 19, 26, 33, 29, 40, 51], 3, 3));
+        assert_eq!(pool.multiply(&b, &a)?, multiply(&b, &a)?);
+        assert!(pool.multiply(&a, &a).is_err());
+
+        let empty = Matrix::new(Vec::<i32>::new(), 0, 2);
+        assert_eq!(pool.multiply(&empty, &b)?.data(), &[] as &[i32]);
+        let (a, b) = (Matrix::<i32>::new([], 2, 0), Matrix::<i32>::new([], 0, 3));
+        assert_eq!(pool.multiply(&a, &b)?, Matrix::new([0; 6], 2, 3));
+        assert_eq!(a * b, Matrix::new([0; 6], 2, 3));
+        Ok(())
+    }
+
+    #[test]
+    fn pool_should_outlive_a_panicking_job() -> Result<()> {
+        let pool = Pool::new(1);
+        pool.execute(|| panic!("job failed"))?;
+        let a = Matrix::new([1, 2, 3, 4], 2, 2);
+        assert_eq!(pool.multiply(&a, &a)?.data(), &[7, 10, 15, 22]);
+        Ok(())
+    }
+
+    #[test]
+    fn transpose_should_swap_rows_and_cols() {
+        let m = Matrix::new([1, 2, 3, 4, 5, 6], 2, 3);
+        assert_eq!(m.transpose(), Matrix::new([1, 4, 2, 5, 3, 6], 3, 2));
+        assert_eq!(m.transpose().transpose(), m);
+    }
+
+    #[test]
+    fn matrix_should_parse() -> Result<()> {
+        let m: Matrix<i32> = "2x3:1,2,3,4,5,6".parse()?;
+        assert_eq!(m, Matrix::new([1, 2, 3, 4, 5, 6], 2, 3));
+
+        assert!("2x3:1,2,3".parse::<Matrix<i32>>().is_err());
+        assert!("2:1,2".parse::<Matrix<i32>>().is_err());
+        assert!("1x2:1,a".parse::<Matrix<i32>>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn dot_product_should_work() -> Result<()> {
+        let a = Vector::new([1, 2, 3]);
+        let b = Vector::new([4, 5, 6]);
+        assert_eq!(dot_product(a, b)?, 32);
+        Ok(())
+    }
+}
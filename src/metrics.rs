@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use anyhow::{anyhow, Result};
+
+/// Thread safe named counters, cheap to clone and share across threads.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    data: Arc<RwLock<HashMap<String, u64>>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn incr(&self, key: impl Into<String>, delta: u64) -> Result<()> {
+        let mut data = self.data.write().map_err(|e| anyhow!(e.to_string()))?;
+        let counter = data.entry(key.into()).or_insert(0);
+        *counter += delta;
+
+        Ok(())
+    }
+
+    pub fn snapshot(&self) -> Result<HashMap<String, u64>> {
+        Ok(self
+            .data
+            .read()
+            .map_err(|e| anyhow!(e.to_string()))?
+            .clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn metrics_should_work() -> Result<()> {
+        let metrics = Metrics::new();
+
+        let handles = (0..4)
+            .map(|_| {
+                let metrics = metrics.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        metrics.incr("req", 1)?;
+                    }
+                    Ok::<_, anyhow::Error>(())
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            handle.join().map_err(|e| anyhow!("{:?}", e))??;
+        }
+        metrics.incr("other", 3)?;
+
+        let snapshot = metrics.snapshot()?;
+        assert_eq!(snapshot.get("req"), Some(&400));
+        assert_eq!(snapshot.get("other"), Some(&3));
+        Ok(())
+    }
+}
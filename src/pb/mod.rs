@@ -0,0 +1,28 @@
+mod demo;
+
+pub use demo::*;
+
+use prost_types::Timestamp;
+use std::time::SystemTime;
+
+impl User {
+    pub fn new(id: u64, name: &str, phone: &str, email: &str) -> Self {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+
+        let timestamp = Some(Timestamp {
+            seconds: now.as_secs() as i64,
+            nanos: now.subsec_nanos() as i32,
+        });
+
+        Self {
+            id,
+            name: name.to_string(),
+            phone: phone.to_string(),
+            email: email.to_string(),
+            created_at: timestamp,
+            updated_at: timestamp,
+        }
+    }
+}
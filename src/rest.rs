@@ -0,0 +1,74 @@
+use anyhow::Result;
+use axum::{
+    extract::State,
+    routing::get,
+    {Json, Router},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tracing::{info, instrument};
+
+pub const DEFAULT_ADDR: &str = "0.0.0.0:3000";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Foo {
+    pub name: String,
+    pub number: u8,
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FooUpdate {
+    name: Option<String>,
+    tags: Option<Vec<String>>,
+}
+
+impl Default for Foo {
+    fn default() -> Self {
+        Self {
+            name: "bar".to_string(),
+            number: 10,
+            tags: vec!["Rust".to_string(), "Golang".to_string()],
+        }
+    }
+}
+
+pub fn app(state: Foo) -> Router {
+    let state = Arc::new(Mutex::new(state));
+
+    Router::new()
+        .route("/", get(index_handler).patch(update_handler))
+        .with_state(state)
+}
+
+pub async fn serve(addr: &str) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Listening on: {}", addr);
+
+    axum::serve(listener, app(Foo::default()).into_make_service()).await?;
+    Ok(())
+}
+
+#[instrument]
+async fn index_handler(State(state): State<Arc<Mutex<Foo>>>) -> Json<Foo> {
+    (*state.lock().unwrap()).clone().into()
+}
+
+#[instrument]
+async fn update_handler(
+    State(state): State<Arc<Mutex<Foo>>>,
+    Json(state_update): Json<FooUpdate>,
+) -> Json<Foo> {
+    let mut instance = state.lock().unwrap();
+
+    if let Some(name) = state_update.name {
+        instance.name = name;
+    }
+
+    if let Some(tags) = state_update.tags {
+        instance.tags = tags;
+    }
+
+    (*instance).clone().into()
+}
@@ -0,0 +1,89 @@
+use std::fmt::{self, Formatter};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, OsRng};
+use chacha20poly1305::{AeadCore, ChaCha20Poly1305, KeyInit};
+
+const KEY: &[u8] = b"01234567890123456789012345678901";
+const NONCE_LEN: usize = 12;
+
+/// A string that is encrypted with ChaCha20Poly1305 whenever it is displayed,
+/// and decrypted when parsed back, so it can be serialized with `DisplayFromStr`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SensitiveData(String);
+
+impl SensitiveData {
+    pub fn new(data: impl Into<String>) -> Self {
+        Self(data.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for SensitiveData {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let cipher = ChaCha20Poly1305::new(KEY.into());
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+        let cipher_text = cipher
+            .encrypt(&nonce, self.0.as_bytes())
+            .map_err(|_| fmt::Error)?;
+        let nonce_cipher_text: Vec<_> = nonce.iter().copied().chain(cipher_text).collect();
+
+        let encoded = BASE64_URL_SAFE_NO_PAD.encode(nonce_cipher_text);
+
+        write!(f, "{}", encoded)
+    }
+}
+
+impl FromStr for SensitiveData {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let decoded = BASE64_URL_SAFE_NO_PAD.decode(s.as_bytes())?;
+        if decoded.len() < NONCE_LEN {
+            return Err(anyhow!("Sensitive data too short."));
+        }
+
+        let cipher = ChaCha20Poly1305::new(KEY.into());
+        let nonce = decoded[..NONCE_LEN].into();
+
+        let decrypted = cipher
+            .decrypt(nonce, &decoded[NONCE_LEN..])
+            .map_err(|e| anyhow!("Decrypt sensitive data error: {}", e))?;
+        let decrypted = String::from_utf8(decrypted)?;
+
+        Ok(Self(decrypted))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sensitive_data_should_round_trip() -> Result<()> {
+        let data = SensitiveData::new("sensitive_data");
+        let encrypted = data.to_string();
+        assert_ne!(encrypted, "sensitive_data");
+
+        let decrypted: SensitiveData = encrypted.parse()?;
+        assert_eq!(decrypted, data);
+        Ok(())
+    }
+
+    #[test]
+    fn sensitive_data_should_reject_invalid_input() {
+        assert!("short".parse::<SensitiveData>().is_err());
+        assert!("not base64!".parse::<SensitiveData>().is_err());
+
+        let mut encrypted = SensitiveData::new("data").to_string();
+        encrypted.replace_range(20..21, if &encrypted[20..21] == "A" { "B" } else { "A" });
+        assert!(encrypted.parse::<SensitiveData>().is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use anyhow::Result;
+use axum::{http::header, routing::get, Router};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{MetricExporter, WithExportConfig};
+use opentelemetry_prometheus::PrometheusExporter;
+use opentelemetry_sdk::{
+    metrics::{PeriodicReader, SdkMeterProvider},
+    runtime, Resource,
+};
+use opentelemetry_semantic_conventions::resource;
+use prometheus::{Encoder, Registry, TextEncoder, TEXT_FORMAT};
+use std::future::Future;
+use tokio::net::TcpListener;
+use tracing::{info, level_filters::LevelFilter};
+use tracing_subscriber::{fmt::Layer, layer::SubscriberExt, util::SubscriberInitExt, Layer as _};
+
+// logs go to stderr so that command output on stdout stays clean
+pub fn init(level: LevelFilter) -> Result<()> {
+    let layer = Layer::new()
+        .with_writer(std::io::stderr)
+        .pretty()
+        .with_filter(level);
+
+    tracing_subscriber::registry().with(layer).try_init()?;
+    Ok(())
+}
+
+// who the telemetry comes from
+pub fn resource() -> Resource {
+    Resource::new(vec![
+        KeyValue::new(resource::SERVICE_NAME, env!("CARGO_PKG_NAME")),
+        KeyValue::new(resource::SERVICE_VERSION, env!("CARGO_PKG_VERSION")),
+    ])
+}
+
+// metrics scraped from `prometheus` and, with an OTLP endpoint, pushed to a collector
+pub fn meter_provider(
+    prometheus: Option<&Prometheus>,
+    otlp_endpoint: Option<&str>,
+) -> Result<SdkMeterProvider> {
+    let mut builder = SdkMeterProvider::builder().with_resource(resource());
+    if let Some(prometheus) = prometheus {
+        builder = builder.with_reader(prometheus.reader()?);
+    }
+    if let Some(endpoint) = otlp_endpoint {
+        let exporter = MetricExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()?;
+        builder = builder.with_reader(PeriodicReader::builder(exporter, runtime::Tokio).build());
+    }
+    Ok(builder.build())
+}
+
+// serve the Prometheus text format at /metrics until `shutdown` resolves
+pub async fn serve_prometheus(
+    addr: &str,
+    prometheus: Prometheus,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Prometheus metrics on http://{}/metrics", addr);
+
+    let app = Router::new().route(
+        "/metrics",
+        get(move || async move {
+            let headers = [(header::CONTENT_TYPE, TEXT_FORMAT)];
+            prometheus
+                .render()
+                .map(|text| (headers, text))
+                .map_err(|e| e.to_string())
+        }),
+    );
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown)
+        .await?;
+    Ok(())
+}
+
+// a registry collected on demand, for a scrape rather than a push
+#[derive(Debug, Clone, Default)]
+pub struct Prometheus {
+    registry: Registry,
+}
+
+impl Prometheus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // a reader for a meter provider, feeding the registry
+    pub fn reader(&self) -> Result<PrometheusExporter> {
+        // every metric would carry the otel_scope_name label otherwise
+        let exporter = opentelemetry_prometheus::exporter()
+            .with_registry(self.registry.clone())
+            .without_scope_info()
+            .build()?;
+        Ok(exporter)
+    }
+
+    pub fn render(&self) -> Result<String> {
+        let mut out = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut out)?;
+        Ok(String::from_utf8(out)?)
+    }
+}
@@ -0,0 +1,144 @@
+use anyhow::{anyhow, Result};
+use futures::{SinkExt, StreamExt};
+use rust_learning::chat::{ChatServer, State};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{net::TcpStream, time};
+use tokio_util::codec::{Framed, LinesCodec};
+
+// longest wait for a line before a test fails rather than hangs
+const LINE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// a server on a free port; without history there is no replay after a login, and sessions end
+// as soon as their connection drops
+async fn spawn_server() -> Result<(SocketAddr, Arc<State>)> {
+    let state = State::default()
+        .with_history(0)
+        .with_resume_grace(Duration::ZERO);
+    let state = Arc::new(state);
+    let server = ChatServer::bind_with("127.0.0.1:0", state.clone()).await?;
+    let addr = server.local_addr()?;
+    tokio::spawn(server.run());
+    Ok((addr, state))
+}
+
+// a simulated client speaking the plain line protocol
+struct TestClient {
+    name: String,
+    conn: Framed<TcpStream, LinesCodec>,
+}
+
+impl TestClient {
+    // log in and wait until the client is in the lobby
+    async fn login(addr: SocketAddr, name: &str) -> Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = Self {
+            name: name.to_string(),
+            conn: Framed::new(stream, LinesCodec::new()),
+        };
+        client.expect("Enter your username: ").await?;
+        client.send(name).await?;
+        client.skip_to("* You joined lobby").await?;
+        Ok(client)
+    }
+
+    async fn send(&mut self, line: &str) -> Result<()> {
+        self.conn.send(line).await?;
+        Ok(())
+    }
+
+    // `None` once the server closed the connection
+    async fn next(&mut self) -> Result<Option<String>> {
+        let line = time::timeout(LINE_TIMEOUT, self.conn.next())
+            .await
+            .map_err(|_| anyhow!("{} got no line within {:?}", self.name, LINE_TIMEOUT))?;
+        Ok(line.transpose()?)
+    }
+
+    // the very next line must be `expected`
+    async fn expect(&mut self, expected: &str) -> Result<()> {
+        match self.next().await? {
+            Some(line) if line == expected => Ok(()),
+            Some(line) => Err(anyhow!(
+                "{} expected {:?}, got {:?}",
+                self.name,
+                expected,
+                line
+            )),
+            None => Err(anyhow!(
+                "{} was disconnected before {:?}",
+                self.name,
+                expected
+            )),
+        }
+    }
+
+    // skip lines until `expected` shows up
+    async fn skip_to(&mut self, expected: &str) -> Result<()> {
+        while let Some(line) = self.next().await? {
+            if line == expected {
+                return Ok(());
+            }
+        }
+        Err(anyhow!(
+            "{} was disconnected before {:?}",
+            self.name,
+            expected
+        ))
+    }
+}
+
+#[tokio::test]
+async fn clients_should_see_joins_broadcasts_and_leaves_in_order() -> Result<()> {
+    let (addr, _state) = spawn_server().await?;
+    let mut alice = TestClient::login(addr, "alice").await?;
+    let mut bob = TestClient::login(addr, "bob").await?;
+    alice.expect("[lobby] bob joined the room").await?;
+    let mut carol = TestClient::login(addr, "carol").await?;
+    alice.expect("[lobby] carol joined the room").await?;
+    bob.expect("[lobby] carol joined the room").await?;
+
+    bob.send("hi all").await?;
+    alice.expect("[lobby] bob: hi all").await?;
+    carol.expect("[lobby] bob: hi all").await?;
+
+    // a dropped connection leaves like /join does
+    drop(carol);
+    alice.expect("[lobby] carol left the room").await?;
+    bob.expect("[lobby] carol left the room").await?;
+    bob.send("/join rust").await?;
+    bob.expect("* You joined rust").await?;
+    alice.expect("[lobby] bob left the room").await?;
+    bob.send("/who").await?;
+    bob.skip_to("* In rust: bob").await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn every_client_should_get_a_broadcast() -> Result<()> {
+    let (addr, state) = spawn_server().await?;
+    let mut clients = Vec::new();
+    for i in 0..20 {
+        clients.push(TestClient::login(addr, &format!("user{}", i)).await?);
+    }
+    assert_eq!(state.who("lobby").len(), 20);
+
+    clients[0].send("hello everyone").await?;
+    for client in &mut clients[1..] {
+        client.skip_to("[lobby] user0: hello everyone").await?;
+    }
+    clients[0].send("/rooms").await?;
+    clients[0].skip_to("* Rooms: lobby (20)").await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn shutdown_should_tell_clients_and_close() -> Result<()> {
+    let (addr, state) = spawn_server().await?;
+    let mut alice = TestClient::login(addr, "alice").await?;
+
+    state.shutdown(Duration::from_secs(1)).await;
+    alice.skip_to("* Server shutting down").await?;
+    assert_eq!(alice.next().await?, None);
+    assert!(TcpStream::connect(addr).await.is_err());
+    Ok(())
+}
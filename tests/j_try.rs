@@ -0,0 +1,30 @@
+use anyhow::Result;
+use rust_learning::j_try;
+
+// breadcrumbs are a process wide switch, so they are tested in their own binary
+
+fn parse(s: &str) -> Result<i32> {
+    Ok(j_try!(s.parse::<i32>(), "parsing {:?}", s))
+}
+
+fn nested(s: &str) -> Result<i32> {
+    Ok(j_try!(parse(s)) + 1)
+}
+
+#[test]
+fn j_try_should_accumulate_breadcrumbs() {
+    rust_learning::set_breadcrumbs(true);
+    let err = nested("x").unwrap_err();
+
+    let chain = err.chain().map(|e| e.to_string()).collect::<Vec<_>>();
+    assert_eq!(chain.len(), 4);
+    assert!(chain[0].starts_with("at tests/j_try.rs:"));
+    assert!(chain[1].starts_with("at tests/j_try.rs:"));
+    assert_ne!(chain[0], chain[1]);
+    assert_eq!(chain[2], "parsing \"x\"");
+    assert_eq!(chain[3], "invalid digit found in string");
+
+    rust_learning::set_breadcrumbs(false);
+    let err = nested("x").unwrap_err();
+    assert_eq!(err.chain().count(), 2);
+}